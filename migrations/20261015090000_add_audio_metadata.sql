@@ -0,0 +1,7 @@
+-- Audio metadata extracted at upload time for audio/* files
+ALTER TABLE files
+    ADD COLUMN audio_duration_ms BIGINT,
+    ADD COLUMN audio_bitrate_kbps INTEGER,
+    ADD COLUMN audio_title VARCHAR(255),
+    ADD COLUMN audio_artist VARCHAR(255),
+    ADD COLUMN audio_album VARCHAR(255);
@@ -0,0 +1,6 @@
+-- Track thumbnail generation outcome so failed thumbnails can be retried later
+ALTER TABLE files ADD COLUMN thumbnail_status VARCHAR(20);
+
+UPDATE files SET thumbnail_status = 'ok' WHERE thumbnail_path IS NOT NULL;
+
+CREATE INDEX idx_files_thumbnail_status ON files(thumbnail_status);
@@ -0,0 +1,4 @@
+-- Flag uploads above the soft size limit for operator review
+ALTER TABLE files ADD COLUMN flagged BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX idx_files_flagged ON files(flagged) WHERE flagged;
@@ -0,0 +1,10 @@
+-- Audit trail of file downloads, views and deletes
+CREATE TABLE access_log (
+    id BIGSERIAL PRIMARY KEY,
+    file_id UUID NOT NULL,
+    action VARCHAR(20) NOT NULL,
+    requester_ip VARCHAR(45),
+    accessed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_access_log_file_id ON access_log(file_id, accessed_at DESC);
@@ -0,0 +1,4 @@
+-- Optional per-file expiry; NULL means the file only follows the default retention
+ALTER TABLE files ADD COLUMN expires_at TIMESTAMPTZ;
+
+CREATE INDEX idx_files_expires_at ON files(expires_at) WHERE expires_at IS NOT NULL;
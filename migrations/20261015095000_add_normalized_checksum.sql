@@ -0,0 +1,4 @@
+-- Checksum of decoded image pixels, used to dedup images differing only in metadata
+ALTER TABLE files ADD COLUMN normalized_checksum TEXT;
+
+CREATE INDEX idx_files_normalized_checksum ON files(normalized_checksum) WHERE normalized_checksum IS NOT NULL;
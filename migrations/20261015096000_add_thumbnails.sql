@@ -0,0 +1,2 @@
+-- Storage keys of every rendered thumbnail size, e.g. {"small": "...", "large": "..."}
+ALTER TABLE files ADD COLUMN thumbnails JSONB;
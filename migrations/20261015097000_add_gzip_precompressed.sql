@@ -0,0 +1,2 @@
+-- Whether a gzip variant of the file is stored next to it as `<key>.gz`
+ALTER TABLE files ADD COLUMN gzip_precompressed BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,16 @@
+-- Resumable uploads in progress; chunks are stored under `chunks/{id}/`
+CREATE TABLE upload_sessions (
+    id UUID PRIMARY KEY,
+    original_filename VARCHAR(255) NOT NULL,
+    mime_type VARCHAR(100) NOT NULL,
+    custom_filename VARCHAR(255),
+    total_size BIGINT NOT NULL,
+    received BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_upload_sessions_updated_at ON upload_sessions(updated_at);
+
+CREATE TRIGGER update_upload_sessions_updated_at BEFORE UPDATE
+    ON upload_sessions FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
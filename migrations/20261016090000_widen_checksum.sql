@@ -0,0 +1,2 @@
+-- BLAKE3 checksums are stored as "blake3:" plus 64 hex characters
+ALTER TABLE files ALTER COLUMN checksum TYPE VARCHAR(80);
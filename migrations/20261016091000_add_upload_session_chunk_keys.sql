@@ -0,0 +1,3 @@
+-- Storage keys of accepted chunks, in byte order; chunks written by losing
+-- concurrent requests are never listed here
+ALTER TABLE upload_sessions ADD COLUMN chunk_keys TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,3 @@
+-- Lease held while a complete session is being stored, so concurrent
+-- finalize requests don't store it twice; expires if the process dies
+ALTER TABLE upload_sessions ADD COLUMN finalizing_until TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,3 @@
+-- Stored filenames are storage keys, so two rows must never share one
+DROP INDEX idx_files_filename;
+CREATE UNIQUE INDEX idx_files_filename ON files(filename);
@@ -0,0 +1,2 @@
+-- MD5 of each upload, recorded alongside its checksum; NULL for older files
+ALTER TABLE files ADD COLUMN md5 VARCHAR(32);
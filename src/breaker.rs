@@ -0,0 +1,166 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tracing::{info, warn};
+
+/// Circuit breaker around storage writes. After `failure_threshold`
+/// consecutive failed (or slower than `latency_threshold`) writes it opens and
+/// rejects uploads for `cooldown`; then a single probe write is let through
+/// (half-open) and its outcome closes or re-opens the breaker.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    state: Arc<Mutex<BreakerState>>,
+    failure_threshold: u32,
+    latency_threshold: Duration,
+    cooldown: Duration,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    probing: bool,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, latency_threshold: Duration, cooldown: Duration) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(BreakerState::default())),
+            failure_threshold,
+            latency_threshold,
+            cooldown,
+        }
+    }
+
+    /// Check whether a write may proceed, returning a permit to record its
+    /// outcome with. Returns the number of seconds callers should wait before
+    /// retrying when the breaker is open.
+    pub fn check(&self) -> Result<WritePermit, u64> {
+        let mut state = self.state.lock().unwrap();
+
+        let Some(opened_at) = state.opened_at else {
+            return Ok(WritePermit { breaker: self.clone(), probe: false });
+        };
+
+        let elapsed = opened_at.elapsed();
+        if elapsed < self.cooldown {
+            return Err((self.cooldown - elapsed).as_secs().max(1));
+        }
+
+        // Half-open: let exactly one probe through
+        if state.probing {
+            return Err(1);
+        }
+        state.probing = true;
+        Ok(WritePermit { breaker: self.clone(), probe: true })
+    }
+
+    fn record(&self, probe: bool, succeeded: bool, latency: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let failed = !succeeded || latency > self.latency_threshold;
+
+        if failed {
+            state.consecutive_failures += 1;
+            if probe || state.consecutive_failures >= self.failure_threshold {
+                if state.opened_at.is_none() || probe {
+                    warn!(
+                        "Storage circuit breaker open after {} failed or slow writes",
+                        state.consecutive_failures
+                    );
+                }
+                state.opened_at = Some(Instant::now());
+            }
+        } else {
+            if state.opened_at.is_some() {
+                info!("Storage circuit breaker closed");
+            }
+            state.consecutive_failures = 0;
+            state.opened_at = None;
+        }
+
+        if probe {
+            state.probing = false;
+        }
+    }
+}
+
+/// A write allowed by `CircuitBreaker::check`. Report its outcome with
+/// `record`; a permit dropped unrecorded (an early return or a cancelled
+/// request) frees the half-open probe slot for the next write.
+#[must_use]
+pub struct WritePermit {
+    breaker: CircuitBreaker,
+    probe: bool,
+}
+
+impl WritePermit {
+    /// Record the outcome of the write.
+    pub fn record(mut self, succeeded: bool, latency: Duration) {
+        let probe = std::mem::take(&mut self.probe);
+        self.breaker.record(probe, succeeded, latency);
+    }
+}
+
+impl Drop for WritePermit {
+    fn drop(&mut self) {
+        if self.probe {
+            self.breaker.state.lock().unwrap().probing = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAST: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(1), Duration::from_secs(60));
+
+        breaker.check().unwrap().record(false, FAST);
+        breaker.check().unwrap().record(true, FAST);
+        breaker.check().unwrap().record(false, FAST);
+        assert!(breaker.check().is_ok(), "a success resets the failure count");
+
+        breaker.check().unwrap().record(false, FAST);
+        let retry_after = breaker.check().err().expect("breaker should be open");
+        assert!((1..=60).contains(&retry_after));
+    }
+
+    #[test]
+    fn slow_writes_count_as_failures() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(10), Duration::from_secs(60));
+
+        breaker.check().unwrap().record(true, Duration::from_millis(20));
+        assert!(breaker.check().is_err());
+    }
+
+    #[test]
+    fn half_open_lets_a_single_probe_through() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(1), Duration::ZERO);
+        breaker.check().unwrap().record(false, FAST);
+
+        let probe = breaker.check().expect("cooldown elapsed, probe allowed");
+        assert_eq!(breaker.check().err(), Some(1));
+
+        probe.record(true, FAST);
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_ok(), "closed breaker allows every write");
+    }
+
+    #[test]
+    fn dropping_an_unrecorded_probe_frees_the_slot() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(1), Duration::ZERO);
+        breaker.check().unwrap().record(false, FAST);
+
+        let probe = breaker.check().unwrap();
+        assert!(breaker.check().is_err());
+        drop(probe);
+
+        assert!(breaker.check().is_ok());
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use dashmap::DashMap;
+use tokio::fs;
+use tracing::{debug, error};
+
+use crate::{models::CacheStats, utils::calculate_sha256};
+
+/// Subdirectory of `DOWNLOAD_CACHE_DIR` owned by the cache.
+const CACHE_SUBDIR: &str = "download-cache";
+
+/// Size-bounded local disk cache of recently served remote (S3) objects.
+/// Entries expire after `ttl` (if set) and the least recently used entries
+/// are evicted once `max_bytes` is exceeded.
+#[derive(Clone)]
+pub struct DownloadCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    ttl: Option<Duration>,
+    entries: Arc<DashMap<String, CacheEntry>>,
+    total_bytes: Arc<AtomicU64>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+struct CacheEntry {
+    path: PathBuf,
+    size: u64,
+    inserted_at: Instant,
+    last_access: Instant,
+}
+
+impl CacheEntry {
+    fn is_expired(&self, ttl: Option<Duration>) -> bool {
+        ttl.is_some_and(|ttl| self.inserted_at.elapsed() >= ttl)
+    }
+}
+
+impl DownloadCache {
+    /// Create the cache under `<dir>/download-cache`, discarding anything left
+    /// there by a previous run. Nothing else in `dir` is touched.
+    pub async fn new(dir: &str, max_bytes: u64, ttl: Option<Duration>) -> std::io::Result<Self> {
+        let dir = PathBuf::from(dir).join(CACHE_SUBDIR);
+        if fs::try_exists(&dir).await? {
+            fs::remove_dir_all(&dir).await?;
+        }
+        fs::create_dir_all(&dir).await?;
+
+        Ok(Self {
+            dir,
+            max_bytes,
+            ttl,
+            entries: Arc::new(DashMap::new()),
+            total_bytes: Arc::new(AtomicU64::new(0)),
+            hits: Arc::new(AtomicU64::new(0)),
+            misses: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// Read a cached object, marking it as recently used.
+    pub async fn get(&self, key: &str) -> Option<Bytes> {
+        let path = match self.entries.get_mut(key) {
+            Some(mut entry) if !entry.is_expired(self.ttl) => {
+                entry.last_access = Instant::now();
+                Some(entry.path.clone())
+            }
+            Some(_) => None,
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+        };
+
+        let Some(path) = path else {
+            debug!("Download cache entry for {} expired", key);
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            self.remove(key).await;
+            return None;
+        };
+
+        match fs::read(&path).await {
+            Ok(data) => {
+                debug!("Download cache hit for {}", key);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(Bytes::from(data))
+            }
+            Err(e) => {
+                error!("Failed to read cached {}: {}", key, e);
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                self.remove(key).await;
+                None
+            }
+        }
+    }
+
+    /// Store an object, evicting older entries to stay under the size bound.
+    /// Objects larger than the whole cache are not stored.
+    pub async fn insert(&self, key: &str, data: &Bytes) {
+        let size = data.len() as u64;
+        if size > self.max_bytes {
+            return;
+        }
+
+        let path = self.dir.join(calculate_sha256(key.as_bytes()));
+        if let Err(e) = fs::write(&path, data).await {
+            error!("Failed to cache {}: {}", key, e);
+            return;
+        }
+
+        let previous = self.entries.insert(
+            key.to_string(),
+            CacheEntry { path, size, inserted_at: Instant::now(), last_access: Instant::now() },
+        );
+        if let Some(previous) = previous {
+            self.total_bytes.fetch_sub(previous.size, Ordering::Relaxed);
+        }
+        self.total_bytes.fetch_add(size, Ordering::Relaxed);
+
+        self.evict().await;
+    }
+
+    /// Drop an object from the cache, e.g. after the file is deleted.
+    pub async fn remove(&self, key: &str) {
+        if let Some((_, entry)) = self.entries.remove(key) {
+            self.total_bytes.fetch_sub(entry.size, Ordering::Relaxed);
+            let _ = fs::remove_file(&entry.path).await;
+        }
+    }
+
+    /// Remove every entry from the cache, returning how many were dropped.
+    pub async fn purge(&self) -> usize {
+        let keys: Vec<String> = self.entries.iter().map(|entry| entry.key().clone()).collect();
+        for key in &keys {
+            self.remove(key).await;
+        }
+        keys.len()
+    }
+
+    /// Current size, entry count and hit rate.
+    pub fn stats(&self) -> CacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        CacheStats {
+            entries: self.entries.len(),
+            size_bytes: self.total_bytes.load(Ordering::Relaxed),
+            max_bytes: self.max_bytes,
+            ttl_seconds: self.ttl.map(|ttl| ttl.as_secs()),
+            hits,
+            misses,
+            hit_rate: if hits + misses > 0 { hits as f64 / (hits + misses) as f64 } else { 0.0 },
+        }
+    }
+
+    /// Drop expired entries, then evict least recently used entries until
+    /// the cache fits in `max_bytes`.
+    async fn evict(&self) {
+        let expired: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.is_expired(self.ttl))
+            .map(|entry| entry.key().clone())
+            .collect();
+        for key in &expired {
+            self.remove(key).await;
+        }
+
+        while self.total_bytes.load(Ordering::Relaxed) > self.max_bytes {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|entry| entry.last_access)
+                .map(|entry| entry.key().clone());
+
+            match oldest {
+                Some(key) => {
+                    debug!("Evicting {} from download cache", key);
+                    self.remove(&key).await;
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn cache_in(dir: &tempfile::TempDir, max_bytes: u64, ttl: Option<Duration>) -> DownloadCache {
+        DownloadCache::new(dir.path().to_str().unwrap(), max_bytes, ttl).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn repeat_reads_are_hits() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache_in(&dir, 1024, None).await;
+
+        assert_eq!(cache.get("files/a.bin").await, None);
+        cache.insert("files/a.bin", &Bytes::from_static(b"hello")).await;
+        assert_eq!(cache.get("files/a.bin").await, Some(Bytes::from_static(b"hello")));
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        assert_eq!((stats.entries, stats.size_bytes), (1, 5));
+    }
+
+    #[tokio::test]
+    async fn eviction_drops_the_least_recently_used_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache_in(&dir, 10, None).await;
+
+        cache.insert("a", &Bytes::from_static(b"aaaa")).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        cache.insert("b", &Bytes::from_static(b"bbbb")).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        cache.insert("c", &Bytes::from_static(b"cccc")).await;
+
+        assert_eq!(cache.get("a").await, None);
+        assert!(cache.get("b").await.is_some());
+        assert!(cache.get("c").await.is_some());
+        assert_eq!(cache.stats().size_bytes, 8);
+    }
+
+    #[tokio::test]
+    async fn objects_larger_than_the_cache_are_not_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache_in(&dir, 4, None).await;
+
+        cache.insert("big", &Bytes::from_static(b"too large")).await;
+        assert_eq!(cache.get("big").await, None);
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[tokio::test]
+    async fn startup_only_clears_the_cache_subdirectory() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("keep.txt"), b"not ours").unwrap();
+        let cache = cache_in(&dir, 1024, None).await;
+        cache.insert("a", &Bytes::from_static(b"aaaa")).await;
+
+        // A restart starts empty but leaves the rest of the directory alone
+        let cache = cache_in(&dir, 1024, None).await;
+        assert_eq!(cache.get("a").await, None);
+        assert!(dir.path().join("keep.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn expired_entries_are_not_served() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache_in(&dir, 1024, Some(Duration::from_millis(50))).await;
+
+        cache.insert("a", &Bytes::from_static(b"old")).await;
+        assert!(cache.get("a").await.is_some());
+
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        assert_eq!(cache.get("a").await, None);
+        assert_eq!(cache.stats().entries, 0);
+        assert_eq!(std::fs::read_dir(dir.path().join(CACHE_SUBDIR)).unwrap().count(), 0);
+    }
+}
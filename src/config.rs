@@ -1,9 +1,203 @@
-use std::env;
+use std::{env, str::FromStr};
 
 use dotenvy::dotenv;
-use validator::Validate;
+use validator::{Validate, ValidationError};
+
+use crate::{models::ThumbnailSize, utils::ConvertFormat};
+
+/// How `upload_file` decides whether an upload duplicates an existing file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupStrategy {
+    /// Same content (checksum) is a duplicate.
+    Checksum,
+    /// Same content and same original filename is a duplicate.
+    ChecksumAndName,
+    /// Never deduplicate; every upload is stored.
+    None,
+}
+
+impl FromStr for DedupStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "checksum" => Ok(DedupStrategy::Checksum),
+            "checksum_and_name" => Ok(DedupStrategy::ChecksumAndName),
+            "none" => Ok(DedupStrategy::None),
+            other => Err(format!("Unknown dedup strategy: {}", other)),
+        }
+    }
+}
+
+/// Body format returned by `/health` when the client expresses no preference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthFormat {
+    Json,
+    Plain,
+}
+
+impl FromStr for HealthFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(HealthFormat::Json),
+            "plain" | "text" => Ok(HealthFormat::Plain),
+            other => Err(format!("Unknown health format: {}", other)),
+        }
+    }
+}
+
+/// Hash used for upload checksums. BLAKE3 digests are stored with a `blake3:`
+/// prefix; SHA-256 digests stay bare so existing rows keep matching.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Sha256,
+    Blake3,
+}
+
+impl ChecksumAlgorithm {
+    /// The algorithm that produced a stored checksum.
+    pub fn of(checksum: &str) -> Self {
+        if checksum.starts_with("blake3:") {
+            ChecksumAlgorithm::Blake3
+        } else {
+            ChecksumAlgorithm::Sha256
+        }
+    }
+}
+
+impl FromStr for ChecksumAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sha256" | "sha-256" => Ok(ChecksumAlgorithm::Sha256),
+            "blake3" => Ok(ChecksumAlgorithm::Blake3),
+            other => Err(format!("Unknown checksum algorithm: {}", other)),
+        }
+    }
+}
+
+/// Storage backend selected with `STORAGE_BACKEND`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageKind {
+    Local,
+    S3,
+    Azure,
+    /// Process-local, non-persistent storage (useful for tests and demos).
+    Memory,
+}
+
+impl StorageKind {
+    /// Value recorded in `files.storage_type`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StorageKind::Local => "local",
+            StorageKind::S3 => "s3",
+            StorageKind::Azure => "azure",
+            StorageKind::Memory => "memory",
+        }
+    }
+}
+
+impl FromStr for StorageKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "local" => Ok(StorageKind::Local),
+            "s3" => Ok(StorageKind::S3),
+            "azure" => Ok(StorageKind::Azure),
+            "memory" => Ok(StorageKind::Memory),
+            "gcs" => Err(format!("Storage backend {} is not supported yet", s)),
+            other => Err(format!("Unknown storage backend: {}", other)),
+        }
+    }
+}
+
+/// Direction for sorted file listings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+impl SortDirection {
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            SortDirection::Asc => "ASC",
+            SortDirection::Desc => "DESC",
+        }
+    }
+}
+
+impl FromStr for SortDirection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "asc" => Ok(SortDirection::Asc),
+            "desc" => Ok(SortDirection::Desc),
+            other => Err(format!("Unknown sort direction: {}", other)),
+        }
+    }
+}
+
+/// How the stored filename (and storage key) of an upload is built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NamingStrategy {
+    /// `{uuid}.{ext}`
+    Uuid,
+    /// `{uuid}_{name}`
+    UuidPrefixedOriginal,
+    /// `{name}`, or `{stem} ({n}).{ext}` when the name is already taken
+    OriginalWithCollisionSuffix,
+    /// `{timestamp}_{name}`
+    TimestampOriginal,
+}
+
+impl FromStr for NamingStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "uuid" => Ok(NamingStrategy::Uuid),
+            "uuid_prefixed_original" => Ok(NamingStrategy::UuidPrefixedOriginal),
+            "original_with_collision_suffix" => Ok(NamingStrategy::OriginalWithCollisionSuffix),
+            "timestamp_original" => Ok(NamingStrategy::TimestampOriginal),
+            other => Err(format!("Unknown naming strategy: {}", other)),
+        }
+    }
+}
+
+/// Corner (or center) of an image where the watermark is placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatermarkPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+impl FromStr for WatermarkPosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace('_', "-").as_str() {
+            "top-left" => Ok(WatermarkPosition::TopLeft),
+            "top-right" => Ok(WatermarkPosition::TopRight),
+            "bottom-left" => Ok(WatermarkPosition::BottomLeft),
+            "bottom-right" => Ok(WatermarkPosition::BottomRight),
+            "center" => Ok(WatermarkPosition::Center),
+            other => Err(format!("Unknown watermark position: {}", other)),
+        }
+    }
+}
 
 #[derive(Debug, Clone, Validate)]
+#[validate(schema(function = "validate_azure_credentials"))]
 pub struct Config {
     pub database_url: String,
     pub s3_endpoint: Option<String>,
@@ -11,10 +205,141 @@ pub struct Config {
     pub s3_bucket: String,
     pub s3_access_key: String,
     pub s3_secret_key: String,
+    pub azure_account: String,
+    pub azure_container: String,
+    pub azure_key: String,
     #[validate(range(min = 1, max = 104857600))] // Max 100MB
     pub max_file_size: u64,
+    /// Uploads above this size are accepted but flagged and logged; `None` disables flagging.
+    pub soft_max_file_size: Option<u64>,
+    /// Largest file loaded into memory to build a transformed (non-binary) response,
+    /// such as HTML previews and charset-transcoded downloads, in bytes.
+    pub max_inline_response_bytes: u64,
+    /// Lifetime of URLs returned by `GET /files/{id}/presigned`, in seconds.
+    #[validate(range(min = 1, max = 604800))] // S3 allows at most 7 days
+    pub presigned_url_expiry_secs: u64,
+    /// Files older than this many days are treated as gone; 0 keeps files indefinitely.
+    pub default_retention_days: u32,
+    /// How often expired files are deleted from storage and the database, in seconds.
+    #[validate(range(min = 1))]
+    pub cleanup_interval_secs: u64,
+    /// Resumable upload sessions idle for this many minutes are discarded with their chunks.
+    #[validate(range(min = 1, max = 525600))]
+    pub upload_session_timeout_minutes: u64,
+    /// Free disk space to keep in reserve beyond each upload, in bytes (local storage).
+    pub disk_headroom_bytes: u64,
+    /// Accepted file extensions; empty means any extension is allowed.
     pub allowed_extensions: Vec<String>,
-    pub use_s3: bool,
+    /// Where file contents are stored.
+    pub storage_backend: StorageKind,
+    /// Prefix applied to every storage key (e.g. `file-service/`); empty for none.
+    pub storage_prefix: String,
+    /// Storage key prefix served read-only at `GET /public/{path}` (e.g. `public/`);
+    /// `None` disables the endpoint.
+    pub public_prefix: Option<String>,
+    /// Write uploads under `staging/` and move them into place only after the database insert.
+    /// Always on with the `original_with_collision_suffix` naming strategy.
+    pub staged_uploads: bool,
+    /// Uploads declaring a length above this many bytes are streamed to storage
+    /// instead of being buffered in memory; they skip thumbnails and audio metadata.
+    pub stream_upload_threshold: u64,
+    /// Age after which abandoned staged or streamed uploads are deleted, in minutes.
+    #[validate(range(min = 1))]
+    pub staging_max_age_minutes: u64,
+    pub admin_token: Option<String>,
+    /// Prefix prepended to every URL returned to clients (e.g. `/api` behind a proxy).
+    /// QR codes need absolute URLs, so a relative value is resolved against the request's host.
+    pub public_base_url: String,
+    /// Inspect zip uploads' central directory and reject likely zip bombs.
+    pub inspect_archives: bool,
+    /// Maximum allowed uncompressed/compressed ratio for inspected archives.
+    #[validate(range(min = 1))]
+    pub max_archive_ratio: u64,
+    /// Maximum allowed total uncompressed size (bytes) for inspected archives.
+    pub max_archive_uncompressed_size: u64,
+    pub dedup_strategy: DedupStrategy,
+    /// Dedup images by their decoded pixels, so copies differing only in
+    /// metadata (e.g. EXIF) match. Costs a full decode per image upload;
+    /// streamed and resumable uploads aren't decoded and dedup by bytes only.
+    pub dedup_normalize_images: bool,
+    pub health_format: HealthFormat,
+    /// Algorithm used to checksum new uploads (and files backfilled without one).
+    pub checksum_algorithm: ChecksumAlgorithm,
+    /// How stored filenames are generated from the uploaded (or custom) name.
+    pub naming_strategy: NamingStrategy,
+    /// Seconds a connection may take to send request headers (also bounds idle keep-alive).
+    #[validate(range(min = 1))]
+    pub header_read_timeout_secs: u64,
+    /// Number of recent log lines kept in memory for `GET /admin/logs/recent`.
+    #[validate(range(min = 1, max = 10000))]
+    pub log_buffer_lines: usize,
+    /// MIME type stored when an upload carries no content type.
+    #[validate(custom(function = "validate_mime"))]
+    pub default_mime_type: String,
+    /// Multipart field name carrying the uploaded file.
+    pub upload_field_name: String,
+    /// Reject unknown `?fields=` names with `400` instead of ignoring them.
+    pub strict_fields: bool,
+    /// Direction used for `?sort=` fields given without an explicit `:asc`/`:desc`.
+    pub default_sort_direction: SortDirection,
+    /// Maximum number of fields accepted in a multipart upload.
+    #[validate(range(min = 1))]
+    pub max_multipart_fields: usize,
+    /// Serve previewable files inline when the request comes from a browser.
+    pub smart_disposition: bool,
+    /// Verify every download against its stored checksum.
+    pub verify_downloads: bool,
+    /// Store a gzip variant of compressible uploads and serve it to clients
+    /// sending `Accept-Encoding: gzip`.
+    pub precompress_uploads: bool,
+    /// Maximum number of in-flight S3 requests (bounds open connections).
+    #[validate(range(min = 1))]
+    pub s3_max_concurrent_requests: usize,
+    /// Timeout for establishing a connection to S3, in milliseconds.
+    pub s3_connect_timeout_ms: u64,
+    /// Timeout for reading a response from S3, in milliseconds.
+    pub s3_read_timeout_ms: u64,
+    /// Timeout for a whole S3 operation including retries, in seconds.
+    pub s3_operation_timeout_secs: u64,
+    /// Encoding of generated thumbnails (`jpeg`, `png` or `webp`).
+    pub thumbnail_format: ConvertFormat,
+    /// Bounding box thumbnails are scaled into, preserving aspect ratio.
+    #[validate(range(min = 16, max = 2000))]
+    pub thumbnail_width: u32,
+    #[validate(range(min = 16, max = 2000))]
+    pub thumbnail_height: u32,
+    /// Bounding boxes of the small and large thumbnail sizes; medium uses
+    /// `thumbnail_width` x `thumbnail_height`.
+    #[validate(range(min = 16, max = 2000))]
+    pub thumbnail_small_size: u32,
+    #[validate(range(min = 16, max = 2000))]
+    pub thumbnail_large_size: u32,
+    /// Image overlaid on served thumbnails; originals are never modified.
+    pub watermark_image_path: Option<String>,
+    pub watermark_position: WatermarkPosition,
+    #[validate(range(min = 0.0, max = 1.0))]
+    pub watermark_opacity: f32,
+    /// Header carrying the request correlation ID, accepted inbound and echoed outbound.
+    pub request_id_header: String,
+    /// Response headers browsers may read cross-origin (`Access-Control-Expose-Headers`),
+    /// in addition to the request ID header.
+    pub cors_expose_headers: Vec<String>,
+    /// Maximum simultaneous downloads of a single file; 0 disables the limit.
+    pub max_concurrent_downloads_per_file: usize,
+    /// Directory for the local cache of downloaded S3/Azure objects, kept in its
+    /// `download-cache` subdirectory; `None` disables it.
+    pub download_cache_dir: Option<String>,
+    /// Maximum total size of the download cache, in bytes.
+    #[validate(range(min = 1))]
+    pub download_cache_max_bytes: u64,
+    /// Maximum age of a download cache entry, in seconds; 0 keeps entries until evicted.
+    pub download_cache_ttl_seconds: u64,
+    /// Consecutive failed or slow storage writes before uploads are rejected; 0 disables the breaker.
+    pub storage_breaker_failure_threshold: u32,
+    /// Storage writes slower than this count as failures, in milliseconds.
+    pub storage_breaker_latency_ms: u64,
+    /// How long uploads are rejected once the breaker opens, in seconds.
+    pub storage_breaker_cooldown_secs: u64,
 }
 
 impl Config {
@@ -22,29 +347,240 @@ impl Config {
     pub fn from_env() -> Result<Self, env::VarError> {
         // Load environment variables from `.env` file (if it exists)
         dotenv().ok();
+        Self::from_vars(env::var)
+    }
+
+    /// Build the configuration from `var`, which looks up a variable by name.
+    fn from_vars(var: impl Fn(&'static str) -> Result<String, env::VarError>) -> Result<Self, env::VarError> {
+        let allowed_extensions = parse_allowed_extensions(
+            &var("ALLOWED_EXTENSIONS").unwrap_or_else(|_| "jpg,jpeg,png,gif,pdf,doc,docx,txt".to_string()),
+        );
 
-        let allowed_extensions = env::var("ALLOWED_EXTENSIONS")
-            .unwrap_or_else(|_| "jpg,jpeg,png,gif,pdf,doc,docx,txt".to_string())
-            .split(',')
-            .map(|s| s.to_lowercase())
-            .collect();
+        let storage_backend = parse_storage_kind(
+            var("STORAGE_BACKEND").ok().as_deref(),
+            var("USE_S3").ok().as_deref(),
+        )
+        .expect("Invalid STORAGE_BACKEND");
 
         let config = Config {
-            database_url: env::var("DATABASE_URL")?,
-            s3_endpoint: env::var("S3_ENDPOINT").ok(),
-            s3_region: env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
-            s3_bucket: env::var("S3_BUCKET").unwrap_or_else(|_| "file-service".to_string()),
-            s3_access_key: env::var("S3_ACCESS_KEY").unwrap_or_else(|_| "minioadmin".to_string()),
-            s3_secret_key: env::var("S3_SECRET_KEY").unwrap_or_else(|_| "minioadmin".to_string()),
-            max_file_size: env::var("MAX_FILE_SIZE")
+            database_url: var("DATABASE_URL")?,
+            s3_endpoint: var("S3_ENDPOINT").ok(),
+            s3_region: var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+            s3_bucket: var("S3_BUCKET").unwrap_or_else(|_| "file-service".to_string()),
+            s3_access_key: var("S3_ACCESS_KEY").unwrap_or_else(|_| "minioadmin".to_string()),
+            s3_secret_key: var("S3_SECRET_KEY").unwrap_or_else(|_| "minioadmin".to_string()),
+            azure_account: var("AZURE_ACCOUNT").unwrap_or_default(),
+            azure_container: var("AZURE_CONTAINER").unwrap_or_else(|_| "file-service".to_string()),
+            azure_key: var("AZURE_KEY").unwrap_or_default(),
+            max_file_size: var("MAX_FILE_SIZE")
                 .unwrap_or_else(|_| "10485760".to_string())
                 .parse()
                 .unwrap_or(10_485_760),
+            soft_max_file_size: var("SOFT_MAX_FILE_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            max_inline_response_bytes: var("MAX_INLINE_RESPONSE_BYTES")
+                .unwrap_or_else(|_| "1048576".to_string())
+                .parse()
+                .unwrap_or(1_048_576),
+            presigned_url_expiry_secs: var("PRESIGNED_URL_EXPIRY_SECS")
+                .unwrap_or_else(|_| "900".to_string())
+                .parse()
+                .unwrap_or(900),
+            default_retention_days: var("DEFAULT_RETENTION_DAYS")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap_or(0),
+            cleanup_interval_secs: var("CLEANUP_INTERVAL_SECS")
+                .unwrap_or_else(|_| "300".to_string())
+                .parse()
+                .unwrap_or(300),
+            upload_session_timeout_minutes: var("UPLOAD_SESSION_TIMEOUT_MINUTES")
+                .unwrap_or_else(|_| "1440".to_string())
+                .parse()
+                .unwrap_or(1440),
+            disk_headroom_bytes: var("DISK_HEADROOM_BYTES")
+                .unwrap_or_else(|_| "104857600".to_string())
+                .parse()
+                .unwrap_or(104_857_600),
             allowed_extensions,
-            use_s3: env::var("USE_S3")
+            storage_backend,
+            storage_prefix: match var("STORAGE_PREFIX").unwrap_or_default().trim_matches('/') {
+                "" => String::new(),
+                prefix => format!("{}/", prefix),
+            },
+            public_prefix: match var("PUBLIC_PREFIX").unwrap_or_default().trim_matches('/') {
+                "" => None,
+                prefix => Some(format!("{}/", prefix)),
+            },
+            staged_uploads: var("STAGED_UPLOADS")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            stream_upload_threshold: var("STREAM_UPLOAD_THRESHOLD")
+                .unwrap_or_else(|_| "8388608".to_string())
+                .parse()
+                .unwrap_or(8_388_608),
+            staging_max_age_minutes: var("STAGING_MAX_AGE_MINUTES")
+                .unwrap_or_else(|_| "60".to_string())
+                .parse()
+                .unwrap_or(60),
+            admin_token: var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()),
+            public_base_url: var("PUBLIC_BASE_URL")
+                .unwrap_or_default()
+                .trim_end_matches('/')
+                .to_string(),
+            inspect_archives: var("INSPECT_ARCHIVES")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            max_archive_ratio: var("MAX_ARCHIVE_RATIO")
+                .unwrap_or_else(|_| "100".to_string())
+                .parse()
+                .unwrap_or(100),
+            max_archive_uncompressed_size: var("MAX_ARCHIVE_UNCOMPRESSED_SIZE")
+                .unwrap_or_else(|_| "1073741824".to_string())
+                .parse()
+                .unwrap_or(1_073_741_824),
+            download_cache_ttl_seconds: var("DOWNLOAD_CACHE_TTL_SECONDS")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap_or(0),
+            storage_breaker_failure_threshold: var("STORAGE_BREAKER_FAILURE_THRESHOLD")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse()
+                .unwrap_or(5),
+            storage_breaker_latency_ms: var("STORAGE_BREAKER_LATENCY_MS")
+                .unwrap_or_else(|_| "10000".to_string())
+                .parse()
+                .unwrap_or(10_000),
+            storage_breaker_cooldown_secs: var("STORAGE_BREAKER_COOLDOWN_SECS")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()
+                .unwrap_or(30),
+            dedup_strategy: var("DEDUP_STRATEGY")
+                .unwrap_or_else(|_| "checksum".to_string())
+                .parse()
+                .unwrap_or(DedupStrategy::Checksum),
+            dedup_normalize_images: var("DEDUP_NORMALIZE_IMAGES")
                 .unwrap_or_else(|_| "false".to_string())
                 .parse()
                 .unwrap_or(false),
+            health_format: var("HEALTH_FORMAT")
+                .unwrap_or_else(|_| "json".to_string())
+                .parse()
+                .unwrap_or(HealthFormat::Json),
+            checksum_algorithm: var("CHECKSUM_ALGORITHM")
+                .unwrap_or_else(|_| "sha256".to_string())
+                .parse()
+                .unwrap_or(ChecksumAlgorithm::Sha256),
+            thumbnail_format: var("THUMBNAIL_FORMAT")
+                .unwrap_or_else(|_| "jpeg".to_string())
+                .parse()
+                .unwrap_or(ConvertFormat::Jpeg),
+            thumbnail_width: var("THUMBNAIL_WIDTH")
+                .unwrap_or_else(|_| "200".to_string())
+                .parse()
+                .unwrap_or(200),
+            thumbnail_height: var("THUMBNAIL_HEIGHT")
+                .unwrap_or_else(|_| "200".to_string())
+                .parse()
+                .unwrap_or(200),
+            thumbnail_small_size: var("THUMBNAIL_SMALL_SIZE")
+                .unwrap_or_else(|_| "100".to_string())
+                .parse()
+                .unwrap_or(100),
+            thumbnail_large_size: var("THUMBNAIL_LARGE_SIZE")
+                .unwrap_or_else(|_| "800".to_string())
+                .parse()
+                .unwrap_or(800),
+            naming_strategy: var("NAMING_STRATEGY")
+                .unwrap_or_else(|_| "uuid_prefixed_original".to_string())
+                .parse()
+                .unwrap_or(NamingStrategy::UuidPrefixedOriginal),
+            header_read_timeout_secs: var("HEADER_READ_TIMEOUT_SECS")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()
+                .unwrap_or(30),
+            log_buffer_lines: var("LOG_BUFFER_LINES")
+                .unwrap_or_else(|_| "200".to_string())
+                .parse()
+                .unwrap_or(200),
+            default_mime_type: var("DEFAULT_MIME_TYPE")
+                .unwrap_or_else(|_| "application/octet-stream".to_string()),
+            upload_field_name: var("UPLOAD_FIELD_NAME")
+                .ok()
+                .filter(|name| !name.is_empty())
+                .unwrap_or_else(|| "file".to_string()),
+            strict_fields: var("STRICT_FIELDS")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            default_sort_direction: var("DEFAULT_SORT_DIRECTION")
+                .unwrap_or_else(|_| "desc".to_string())
+                .parse()
+                .unwrap_or(SortDirection::Desc),
+            max_multipart_fields: var("MAX_MULTIPART_FIELDS")
+                .unwrap_or_else(|_| "100".to_string())
+                .parse()
+                .unwrap_or(100),
+            smart_disposition: var("SMART_DISPOSITION")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            verify_downloads: var("VERIFY_DOWNLOADS")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            precompress_uploads: var("PRECOMPRESS_UPLOADS")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            s3_max_concurrent_requests: var("S3_MAX_CONCURRENT_REQUESTS")
+                .unwrap_or_else(|_| "64".to_string())
+                .parse()
+                .unwrap_or(64),
+            s3_connect_timeout_ms: var("S3_CONNECT_TIMEOUT_MS")
+                .unwrap_or_else(|_| "3100".to_string())
+                .parse()
+                .unwrap_or(3100),
+            s3_read_timeout_ms: var("S3_READ_TIMEOUT_MS")
+                .unwrap_or_else(|_| "30000".to_string())
+                .parse()
+                .unwrap_or(30_000),
+            s3_operation_timeout_secs: var("S3_OPERATION_TIMEOUT_SECS")
+                .unwrap_or_else(|_| "300".to_string())
+                .parse()
+                .unwrap_or(300),
+            watermark_image_path: var("WATERMARK_IMAGE_PATH").ok().filter(|p| !p.is_empty()),
+            watermark_position: var("WATERMARK_POSITION")
+                .unwrap_or_else(|_| "bottom-right".to_string())
+                .parse()
+                .unwrap_or(WatermarkPosition::BottomRight),
+            watermark_opacity: var("WATERMARK_OPACITY")
+                .unwrap_or_else(|_| "0.5".to_string())
+                .parse()
+                .unwrap_or(0.5),
+            request_id_header: var("REQUEST_ID_HEADER")
+                .ok()
+                .filter(|h| !h.is_empty())
+                .unwrap_or_else(|| "x-request-id".to_string())
+                .to_lowercase(),
+            cors_expose_headers: var("CORS_EXPOSE_HEADERS")
+                .unwrap_or_else(|_| "etag,x-total-count,link,location,content-disposition,retry-after".to_string())
+                .split(',')
+                .map(|s| s.trim().to_lowercase())
+                .filter(|s| !s.is_empty())
+                .collect(),
+            max_concurrent_downloads_per_file: var("MAX_CONCURRENT_DOWNLOADS_PER_FILE")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap_or(0),
+            download_cache_dir: var("DOWNLOAD_CACHE_DIR").ok().filter(|d| !d.is_empty()),
+            download_cache_max_bytes: var("DOWNLOAD_CACHE_MAX_BYTES")
+                .unwrap_or_else(|_| "1073741824".to_string())
+                .parse()
+                .unwrap_or(1_073_741_824),
         };
         
         // Validate configuration values (e.g. file size range)
@@ -52,4 +588,125 @@ impl Config {
         Ok(config)
 
     }
-}
\ No newline at end of file
+
+    /// Bounding box `(width, height)` a thumbnail size is rendered into.
+    pub fn thumbnail_dimensions(&self, size: ThumbnailSize) -> (u32, u32) {
+        match size {
+            ThumbnailSize::Small => (self.thumbnail_small_size, self.thumbnail_small_size),
+            ThumbnailSize::Medium => (self.thumbnail_width, self.thumbnail_height),
+            ThumbnailSize::Large => (self.thumbnail_large_size, self.thumbnail_large_size),
+        }
+    }
+
+    /// Build a client-facing URL for the given path using `public_base_url`.
+    pub fn public_url(&self, path: &str) -> String {
+        format!("{}{}", self.public_base_url, path)
+    }
+
+    /// Defaults overridden by `vars`, using in-memory storage unless `vars` picks
+    /// another backend. The process environment is never read.
+    #[cfg(test)]
+    pub(crate) fn for_tests(vars: &[(&str, &str)]) -> Self {
+        Self::from_vars(|key| match vars.iter().find(|(name, _)| *name == key) {
+            Some((_, value)) => Ok(value.to_string()),
+            None if key == "DATABASE_URL" => Ok("postgres://localhost/file_service_test".to_string()),
+            None if key == "STORAGE_BACKEND" => Ok("memory".to_string()),
+            None => Err(env::VarError::NotPresent),
+        })
+        .expect("Failed to load test configuration")
+    }
+}
+
+/// Normalize a comma-separated `ALLOWED_EXTENSIONS` list: entries are trimmed,
+/// lowercased and stripped of a leading dot, and blank entries are dropped.
+/// An explicitly empty list means every extension is accepted.
+fn parse_allowed_extensions(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|s| s.trim().trim_start_matches('.').to_lowercase())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Storage backend from `STORAGE_BACKEND`, or from the deprecated `USE_S3`
+/// flag when it is unset or empty.
+fn parse_storage_kind(backend: Option<&str>, use_s3: Option<&str>) -> Result<StorageKind, String> {
+    match backend {
+        Some(backend) if !backend.is_empty() => backend.parse(),
+        _ => {
+            let use_s3: bool = use_s3.unwrap_or("false").parse().unwrap_or(false);
+            Ok(if use_s3 { StorageKind::S3 } else { StorageKind::Local })
+        }
+    }
+}
+
+/// The Azure backend signs every request with the account name and key.
+fn validate_azure_credentials(config: &Config) -> Result<(), ValidationError> {
+    if config.storage_backend == StorageKind::Azure
+        && (config.azure_account.trim().is_empty() || config.azure_key.trim().is_empty())
+    {
+        return Err(ValidationError::new("missing_azure_credentials")
+            .with_message("AZURE_ACCOUNT and AZURE_KEY are required for the azure storage backend".into()));
+    }
+    Ok(())
+}
+
+/// Ensure a configured value is a well-formed MIME type.
+fn validate_mime(value: &str) -> Result<(), ValidationError> {
+    value
+        .parse::<mime::Mime>()
+        .map(|_| ())
+        .map_err(|_| ValidationError::new("invalid_mime_type"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_storage_kind_reads_storage_backend() {
+        assert_eq!(parse_storage_kind(Some("local"), None), Ok(StorageKind::Local));
+        assert_eq!(parse_storage_kind(Some("S3"), None), Ok(StorageKind::S3));
+        assert_eq!(parse_storage_kind(Some("azure"), None), Ok(StorageKind::Azure));
+        assert_eq!(parse_storage_kind(Some("memory"), None), Ok(StorageKind::Memory));
+    }
+
+    #[test]
+    fn parse_storage_kind_falls_back_to_use_s3() {
+        assert_eq!(parse_storage_kind(None, None), Ok(StorageKind::Local));
+        assert_eq!(parse_storage_kind(None, Some("true")), Ok(StorageKind::S3));
+        assert_eq!(parse_storage_kind(Some(""), Some("true")), Ok(StorageKind::S3));
+        assert_eq!(parse_storage_kind(None, Some("false")), Ok(StorageKind::Local));
+        assert_eq!(parse_storage_kind(None, Some("yes please")), Ok(StorageKind::Local));
+        // An explicit backend wins over the deprecated flag
+        assert_eq!(parse_storage_kind(Some("local"), Some("true")), Ok(StorageKind::Local));
+    }
+
+    #[test]
+    fn parse_storage_kind_rejects_unsupported_backends() {
+        let gcs = parse_storage_kind(Some("gcs"), None).unwrap_err();
+        assert!(gcs.contains("not supported yet"), "{}", gcs);
+        let unknown = parse_storage_kind(Some("ftp"), Some("true")).unwrap_err();
+        assert!(unknown.contains("Unknown storage backend"), "{}", unknown);
+    }
+
+    #[test]
+    fn parse_allowed_extensions_normalizes_entries() {
+        assert_eq!(parse_allowed_extensions("jpg,PNG, .Pdf ,txt"), vec!["jpg", "png", "pdf", "txt"]);
+        assert_eq!(parse_allowed_extensions(" tar.gz ,,.."), vec!["tar.gz"]);
+    }
+
+    #[test]
+    fn parse_allowed_extensions_accepts_an_empty_list() {
+        assert!(parse_allowed_extensions("").is_empty());
+        assert!(parse_allowed_extensions(" , ,").is_empty());
+    }
+
+    #[test]
+    fn allowed_extensions_default_when_unset() {
+        let config = Config::for_tests(&[]);
+        assert_eq!(config.allowed_extensions, vec!["jpg", "jpeg", "png", "gif", "pdf", "doc", "docx", "txt"]);
+        let config = Config::for_tests(&[("ALLOWED_EXTENSIONS", "")]);
+        assert!(config.allowed_extensions.is_empty());
+    }
+}
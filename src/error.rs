@@ -1,5 +1,5 @@
 use axum::{Json, 
-    http::StatusCode, 
+    http::{StatusCode, header}, 
     response::IntoResponse
 };
 use serde_json::json;
@@ -23,9 +23,33 @@ pub enum AppError {
     #[error("Unsupported media type: {0}")]
     UnSupportedMediaType(String),
 
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Gone: {0}")]
+    Gone(String),
+
+    #[error("Unprocessable entity: {0}")]
+    UnprocessableEntity(String),
+
     #[error("Multipart error: {0}")]
     MultipartError(String),
 
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Too many requests: {0}")]
+    TooManyRequests(String),
+
+    #[error("Service unavailable: {0}")]
+    ServiceUnavailable(String),
+
+    #[error("Insufficient storage: {0}")]
+    InsufficientStorage(String),
+
+    #[error("Storage degraded; retry after {0}s")]
+    StorageDegraded(u64),
+
     #[error("File processing error: {0}")]
     FileProcessingError(String),
 
@@ -37,15 +61,33 @@ pub enum AppError {
 /// Convert `AppError` into an HTTP response.
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
+        // Tell throttled clients when to try again
+        let retry_after = match &self {
+            AppError::TooManyRequests(_) => Some(1),
+            AppError::StorageDegraded(secs) => Some(*secs),
+            _ => None,
+        };
+
         // Map application errors to HTTP status codes and messages
         let (status, error_message) = match self {
             AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
             AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
             AppError::InternalServerError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
             AppError::PayloadTooLarge(msg) => (StatusCode::PAYLOAD_TOO_LARGE, msg),
+            AppError::Conflict(msg) => (StatusCode::CONFLICT, msg),
+            AppError::Gone(msg) => (StatusCode::GONE, msg),
+            AppError::UnprocessableEntity(msg) => (StatusCode::UNPROCESSABLE_ENTITY, msg),
             AppError::MultipartError(msg) => (StatusCode::BAD_REQUEST, msg),
             AppError::FileProcessingError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
             AppError::UnSupportedMediaType(msg) => (StatusCode::UNSUPPORTED_MEDIA_TYPE, msg),
+            AppError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg),
+            AppError::TooManyRequests(msg) => (StatusCode::TOO_MANY_REQUESTS, msg),
+            AppError::ServiceUnavailable(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg),
+            AppError::InsufficientStorage(msg) => (StatusCode::INSUFFICIENT_STORAGE, msg),
+            AppError::StorageDegraded(_) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Storage is degraded; uploads are temporarily rejected".to_string(),
+            ),
             AppError::DatabaseError(err) => {
                 tracing::error!("Database Error: {:}", err);
                 (
@@ -57,6 +99,14 @@ impl IntoResponse for AppError {
 
         // Return standardized JSON error response
         let body = Json(json!({"error": error_message}));
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+
+        if let Some(secs) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, header::HeaderValue::from(secs));
+        }
+
+        response
     }
 }
\ No newline at end of file
@@ -1,41 +1,119 @@
-use axum::{Json, extract::{Multipart, Path, State}, http::{StatusCode, header}, response::Response};
-use bytes::Bytes;
-use tracing::{error, info};
+use std::{net::SocketAddr, sync::atomic::Ordering, time::{Duration, Instant}};
+
+use axum::{Json, body::Body, extract::{ConnectInfo, Multipart, Path, Query, State}, http::{HeaderMap, StatusCode, header}, response::{IntoResponse, Response}};
+use bytes::{Bytes, BytesMut};
+use chrono_tz::Tz;
+use encoding_rs::Encoding;
+use futures::{SinkExt, Stream, StreamExt, TryStreamExt, channel::mpsc};
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
 use crate::{
-    error::AppError, models::*, state::AppState, storage::Storage, utils::{calculate_sha256, get_file_extension, is_file_mime_type, generate_thumbnail},
+    cache::DownloadCache, config::{ChecksumAlgorithm, DedupStrategy, NamingStrategy}, error::AppError, models::*, query::{count_files, link_header, page, select_all_files, select_files}, state::AppState, storage::{STAGING_PREFIX, Storage, StorageError}, utils::{build_stored_filename, calculate_checksum, calculate_sha256, ContentDigests, UploadHasher, get_file_extension, is_file_mime_type, detect_mime, matches_detected_type, TransferEncoding, generate_thumbnail, mime_category, inspect_zip, storage_relative_path, extract_audio_metadata, is_inline_previewable, is_compressible, accepts_encoding, gzip, metadata_etag, etag_matches, ConvertFormat, convert_image, apply_watermark, transcode_text, generate_qr_png, normalized_image_checksum, preview_syntax, render_preview_html},
 };
 
 
 /// Upload a file using multipart/form-data.
 pub async fn upload_file(
     State(state): State<AppState>,
+    headers: HeaderMap,
     mut multipart: Multipart,
-) -> Result<Json<UploadResponse>, AppError>{
+) -> Result<Response, AppError>{
+    let request_length: Option<u64> = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok());
+
     // Temporary holders for multipart fields
-    let mut file_data: Option<Bytes> = None;
+    let mut content: Option<UploadContent> = None;
+    let mut digests: Option<ContentDigests> = None;
     let mut original_filename: Option<String> = None;
     let mut mime_type: Option<String> = None;
-    let mut file_size: u64 = 0;
     let mut custom_filename: Option<String> = None;
+    let mut content_type_override: Option<String> = None;
+    let mut declared_size: Option<u64> = None;
+    let mut ttl_seconds: Option<u64> = None;
+    let mut part_length: Option<u64> = None;
+    let mut field_count: usize = 0;
 
     // Parse multipart fields
-    while let Some(field) = multipart.next_field().await.map_err(|e|{
+    while let Some(mut field) = multipart.next_field().await.map_err(|e|{
         error!("Error parsing multipart: {}", e);
         AppError::MultipartError(format!("Failed to parse multipart form: {}",e))})? 
         {
-        match field.name().unwrap_or("") {
-            "file" => {
+        // Stop parsing bodies made of huge numbers of tiny fields
+        field_count += 1;
+        if field_count > state.config.max_multipart_fields {
+            error!("Multipart form exceeded {} fields", state.config.max_multipart_fields);
+            return Err(AppError::BadRequest(format!(
+                "Too many multipart fields (maximum {})",
+                state.config.max_multipart_fields
+            )));
+        }
+
+        let field_name = field.name().unwrap_or("").to_string();
+        match field_name.as_str() {
+            name if name == state.config.upload_field_name => {
                 original_filename = field.file_name().map(|s| s.to_string());
                 mime_type = field.content_type().map(|s| s.to_string());
-                // Read file bytes
-                let data = field.bytes().await.map_err(|e| {
+
+                // Older clients may base64 or quoted-printable encode the part
+                let transfer_encoding = match field.headers().get("content-transfer-encoding") {
+                    Some(value) => {
+                        let value = value.to_str().unwrap_or_default();
+                        TransferEncoding::parse(value).ok_or_else(|| {
+                            AppError::BadRequest(format!("Unsupported Content-Transfer-Encoding: {}", value))
+                        })?
+                    }
+                    None => TransferEncoding::Identity,
+                };
+
+                // An encoded part's Content-Length counts encoded bytes, not file bytes
+                part_length = field
+                    .headers()
+                    .get(header::CONTENT_LENGTH)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.trim().parse().ok())
+                    .filter(|_| transfer_encoding == TransferEncoding::Identity);
+
+                // Large files go straight to storage instead of being buffered;
+                // zips that must be inspected and encoded parts are always buffered
+                let declared_length = part_length.or(request_length);
+                let inspect_archive = state.config.inspect_archives
+                    && original_filename.as_deref().and_then(get_file_extension).as_deref() == Some("zip");
+                if let Some(length) = declared_length.filter(|length| {
+                    *length > state.config.stream_upload_threshold
+                        && !inspect_archive
+                        && transfer_encoding == TransferEncoding::Identity
+                }) {
+                    let (streamed, streamed_digests) = stream_to_staging(&state, field, length).await?;
+                    content = Some(streamed);
+                    digests = Some(streamed_digests);
+                    continue;
+                }
+
+                // Read file bytes chunk by chunk, hashing them in the same pass
+                let mut data = BytesMut::new();
+                let mut hasher = UploadHasher::new(state.config.checksum_algorithm);
+                while let Some(chunk) = field.chunk().await.map_err(|e| {
                     error!("Error reading file bytes: {}", e);
                     AppError::FileProcessingError(format!("Failed to read the file: {}",e))
-                })?;
-                file_size = data.len() as u64;
-                file_data = Some(data);
+                })? {
+                    hasher.update(&chunk);
+                    data.extend_from_slice(&chunk);
+                }
+
+                if transfer_encoding == TransferEncoding::Identity {
+                    content = Some(UploadContent::Buffered(data.freeze()));
+                    digests = Some(hasher.finalize());
+                } else {
+                    let decoded = transfer_encoding.decode(&data).map_err(|e| {
+                        error!("Failed to decode file part: {}", e);
+                        AppError::BadRequest(format!("Failed to decode file: {}", e))
+                    })?;
+                    digests = Some(ContentDigests::of(&decoded, state.config.checksum_algorithm));
+                    content = Some(UploadContent::Buffered(Bytes::from(decoded)));
+                }
             }
             "filename" => {
                 // Optional custom filename
@@ -45,14 +123,320 @@ pub async fn upload_file(
                     }
                 }
             }
+            "content_type" => {
+                // Optional MIME type overriding the one sent with the file part
+                let value = field.text().await.map_err(|e| {
+                    AppError::MultipartError(format!("Failed to read content_type field: {}", e))
+                })?;
+                let value = value.trim();
+                if !value.is_empty() {
+                    let parsed = value.parse::<mime::Mime>().map_err(|_| {
+                        AppError::BadRequest(format!("Invalid content_type: {}", value))
+                    })?;
+                    content_type_override = Some(parsed.to_string());
+                }
+            }
+            "size" => {
+                // Optional size declared by the client, checked against the bytes received
+                let value = field.text().await.map_err(|e| {
+                    AppError::MultipartError(format!("Failed to read size field: {}", e))
+                })?;
+                let value = value.trim();
+                if !value.is_empty() {
+                    declared_size = Some(value.parse().map_err(|_| {
+                        AppError::BadRequest(format!("Invalid size: {}", value))
+                    })?);
+                }
+            }
+            "ttl_seconds" => {
+                // Optional lifetime after which the file expires and is cleaned up
+                let value = field.text().await.map_err(|e| {
+                    AppError::MultipartError(format!("Failed to read ttl_seconds field: {}", e))
+                })?;
+                let value = value.trim();
+                if !value.is_empty() {
+                    ttl_seconds = Some(value.parse().ok().filter(|ttl| *ttl > 0).ok_or_else(|| {
+                        AppError::BadRequest(format!("Invalid ttl_seconds: {}", value))
+                    })?);
+                }
+            }
             _ => {}
         }
     }
 
+    // Catch truncated uploads: the declared size (field or part Content-Length)
+    // must match what was actually received
+    if let Some(content) = &content {
+        let received = content.size();
+        for declared in [declared_size, part_length].into_iter().flatten() {
+            if declared != received {
+                error!("Declared size {} does not match {} bytes received", declared, received);
+                return Err(AppError::BadRequest(format!(
+                    "Declared size {} does not match {} bytes received",
+                    declared, received
+                )));
+            }
+        }
+    }
+
+    // An explicit content_type field takes precedence over the detected one,
+    // falling back to the configured default when neither is present
+    let mime_type = content_type_override
+        .or(mime_type)
+        .unwrap_or_else(|| state.config.default_mime_type.clone());
+
     // Ensure file exists
-    let file_data = file_data.ok_or_else(|| AppError::BadRequest("No file provided".into()))?;
+    let content = content.ok_or_else(|| AppError::BadRequest("No file provided".into()))?;
+    let digests = digests.ok_or_else(|| AppError::BadRequest("No file provided".into()))?;
     let original_filename = original_filename.ok_or_else(|| AppError::BadRequest("No file provided".into()))?;
 
+    store_file(&state, NewUpload {
+        content,
+        digests,
+        original_filename,
+        mime_type,
+        custom_filename,
+        ttl_seconds,
+    }).await
+}
+
+/// Upload a file sent as the raw request body, e.g. `curl --data-binary @file`.
+/// The original filename comes from the `X-Filename` header.
+pub async fn upload_raw(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, AppError> {
+    let original_filename = headers
+        .get("x-filename")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .ok_or_else(|| AppError::BadRequest("Missing X-Filename header".into()))?;
+
+    let mime_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<mime::Mime>().ok())
+        .map(|m| m.to_string())
+        .unwrap_or_else(|| state.config.default_mime_type.clone());
+
+    // Large bodies go straight to storage instead of being buffered
+    let declared_length: Option<u64> = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok());
+    let is_zip = get_file_extension(&original_filename).as_deref() == Some("zip");
+    if let Some(length) = declared_length.filter(|length| {
+        *length > state.config.stream_upload_threshold && !(state.config.inspect_archives && is_zip)
+    }) {
+        let (content, digests) = stream_to_staging(&state, body.into_data_stream(), length).await?;
+        return store_file(&state, NewUpload {
+            content,
+            digests,
+            original_filename,
+            mime_type,
+            custom_filename: None,
+            ttl_seconds: None,
+        }).await;
+    }
+
+    // Read the body chunk by chunk, hashing as it arrives and bailing out
+    // as soon as the size limit is crossed
+    let mut data = BytesMut::new();
+    let mut hasher = UploadHasher::new(state.config.checksum_algorithm);
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.try_next().await.map_err(|e| {
+        error!("Error reading request body: {}", e);
+        AppError::FileProcessingError(format!("Failed to read the file: {}", e))
+    })? {
+        if (data.len() + chunk.len()) as u64 > state.config.max_file_size {
+            return Err(AppError::PayloadTooLarge(format!(
+                "File exceeds maximum limit of {} bytes",
+                state.config.max_file_size
+            )));
+        }
+        hasher.update(&chunk);
+        data.extend_from_slice(&chunk);
+    }
+
+    store_file(&state, NewUpload {
+        content: UploadContent::Buffered(data.freeze()),
+        digests: hasher.finalize(),
+        original_filename,
+        mime_type,
+        custom_filename: None,
+        ttl_seconds: None,
+    }).await
+}
+
+/// A fully received upload, independent of how it was transferred.
+pub(crate) struct NewUpload {
+    pub(crate) content: UploadContent,
+    /// Checksum and MD5, computed while the data was read.
+    pub(crate) digests: ContentDigests,
+    pub(crate) original_filename: String,
+    pub(crate) mime_type: String,
+    pub(crate) custom_filename: Option<String>,
+    /// Seconds until the file expires; `None` keeps it until deleted.
+    pub(crate) ttl_seconds: Option<u64>,
+}
+
+/// Bytes of an upload, held in memory or already streamed to storage.
+pub(crate) enum UploadContent {
+    Buffered(Bytes),
+    /// Written under `STAGING_PREFIX` by `stream_to_staging`. `head` keeps the
+    /// first `SNIFF_LEN` bytes so the content type can still be sniffed.
+    Streamed { path: String, size: u64, head: Bytes },
+}
+
+/// How many leading bytes of a streamed upload are kept for MIME sniffing.
+pub(crate) const SNIFF_LEN: usize = 8192;
+
+/// Append the part of `chunk` that still fits in the first `SNIFF_LEN` bytes.
+pub(crate) fn capture_head(head: &mut BytesMut, chunk: &[u8]) {
+    let take = SNIFF_LEN.saturating_sub(head.len()).min(chunk.len());
+    head.extend_from_slice(&chunk[..take]);
+}
+
+impl UploadContent {
+    fn size(&self) -> u64 {
+        match self {
+            UploadContent::Buffered(data) => data.len() as u64,
+            UploadContent::Streamed { size, .. } => *size,
+        }
+    }
+
+    /// In-memory bytes, if the upload wasn't streamed.
+    fn buffered(&self) -> Option<&Bytes> {
+        match self {
+            UploadContent::Buffered(data) => Some(data),
+            UploadContent::Streamed { .. } => None,
+        }
+    }
+
+    /// Leading bytes to sniff the content type from.
+    fn head(&self) -> &[u8] {
+        match self {
+            UploadContent::Buffered(data) => &data[..],
+            UploadContent::Streamed { head, .. } => &head[..],
+        }
+    }
+}
+
+/// Stream an upload body into a staging object without buffering it, hashing
+/// and enforcing the size limit on the way. Returns the content and its digests.
+async fn stream_to_staging<S, E>(
+    state: &AppState,
+    body: S,
+    declared_length: u64,
+) -> Result<(UploadContent, ContentDigests), AppError>
+where
+    S: Stream<Item = Result<Bytes, E>> + Send,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    ensure_disk_space(state, declared_length).await?;
+    let permit = state
+        .upload_breaker
+        .as_ref()
+        .map(|breaker| breaker.check())
+        .transpose()
+        .map_err(AppError::StorageDegraded)?;
+
+    let path = format!("{}{}", STAGING_PREFIX, Uuid::new_v4());
+    let max_file_size = state.config.max_file_size;
+    let mut hasher = UploadHasher::new(state.config.checksum_algorithm);
+    let mut received: u64 = 0;
+    let mut head = BytesMut::new();
+    let stream = body.map(|chunk| {
+        let chunk = chunk.map_err(std::io::Error::other)?;
+        received += chunk.len() as u64;
+        if received > max_file_size {
+            return Err(std::io::Error::other("upload exceeds the maximum file size"));
+        }
+        hasher.update(&chunk);
+        capture_head(&mut head, &chunk);
+        Ok(chunk)
+    });
+
+    let started = Instant::now();
+    let uploaded = state.storage.upload_stream(&path, Box::pin(stream)).await;
+    let too_large = received > max_file_size;
+    if let Some(permit) = permit {
+        permit.record(uploaded.is_ok() || too_large, started.elapsed());
+    }
+
+    match uploaded {
+        Ok(size) => Ok((UploadContent::Streamed { path, size, head: head.freeze() }, hasher.finalize())),
+        Err(e) => {
+            let _ = state.storage.delete(&path).await;
+            if too_large {
+                return Err(AppError::PayloadTooLarge(format!(
+                    "File exceeds maximum limit of {} bytes",
+                    max_file_size
+                )));
+            }
+            error!("Error streaming upload: {}", e);
+            Err(AppError::InternalServerError("Failed to upload file".into()))
+        }
+    }
+}
+
+/// Refuse an upload of `size` bytes cleanly rather than filling the disk with a partial write.
+pub(crate) async fn ensure_disk_space(state: &AppState, size: u64) -> Result<(), AppError> {
+    let available = state.storage.available_space().await.map_err(|e| {
+        error!("Failed to check available storage space: {}", e);
+        AppError::InternalServerError("Failed to check available storage space".into())
+    })?;
+    check_disk_space(available, size, state.config.disk_headroom_bytes)
+}
+
+/// Whether `available` bytes (`None` when the backend can't tell) fit `size`
+/// bytes plus the configured headroom.
+fn check_disk_space(available: Option<u64>, size: u64, headroom: u64) -> Result<(), AppError> {
+    if let Some(available) = available {
+        let required = size.saturating_add(headroom);
+        if available < required {
+            error!("Insufficient disk space: {} bytes available, {} required", available, required);
+            return Err(AppError::InsufficientStorage(
+                "Not enough free disk space to store this file".into(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Validate, deduplicate, store and record an upload.
+/// Shared by the multipart and raw-body upload handlers.
+/// Responds `201 Created` with a `Location` header for new files and `200`
+/// when the upload was deduplicated against an existing file.
+pub(crate) async fn store_file(state: &AppState, upload: NewUpload) -> Result<Response, AppError> {
+    // A streamed upload is already in staging; drop it unless it was stored
+    let result = store_upload(state, &upload).await;
+    if let UploadContent::Streamed { path, .. } = &upload.content {
+        if !matches!(&result, Ok(response) if response.status() == StatusCode::CREATED) {
+            let _ = state.storage.delete(path).await;
+        }
+    }
+    result
+}
+
+/// The body of `store_file`, which cleans up after streamed uploads.
+async fn store_upload(state: &AppState, upload: &NewUpload) -> Result<Response, AppError> {
+    let NewUpload { content, digests: ContentDigests { checksum, md5 }, original_filename, mime_type, custom_filename, ttl_seconds } = upload;
+    let file_size = content.size();
+
+    let expires_at = match ttl_seconds {
+        Some(ttl) => Some(
+            i64::try_from(*ttl)
+                .ok()
+                .and_then(chrono::Duration::try_seconds)
+                .and_then(|ttl| chrono::Utc::now().checked_add_signed(ttl))
+                .ok_or_else(|| AppError::BadRequest(format!("ttl_seconds {} is too large", ttl)))?,
+        ),
+        None => None,
+    };
+
     // Enforce maximum file size
     if file_size > state.config.max_file_size {
         error!(
@@ -67,11 +451,27 @@ pub async fn upload_file(
         )));
     }
 
+    // Uploads above the soft limit are accepted but flagged for review
+    let flagged = state
+        .config
+        .soft_max_file_size
+        .is_some_and(|soft_max| file_size > soft_max);
+
+    if flagged {
+        warn!(
+            "File size {} exceeds soft limit of {} bytes; flagging for review",
+            file_size,
+            state.config.soft_max_file_size.unwrap_or_default()
+        );
+    }
+
     // Validate file extension
     let extension = get_file_extension(&original_filename)
         .ok_or_else(|| AppError::BadRequest("Invalid file extension".into()))?;
 
-    if !state.config.allowed_extensions.contains(&extension) {
+    if !state.config.allowed_extensions.is_empty()
+        && !state.config.allowed_extensions.contains(&extension)
+    {
         error!("File extension .{} is not allowed",extension);
 
         return Err(AppError::UnSupportedMediaType(format!(
@@ -80,117 +480,386 @@ pub async fn upload_file(
         )));
     }
 
-    // Generate unique file ID and filename
+    // Don't trust the client's MIME type: sniff the content (or, for streamed
+    // uploads, its first bytes) and record what it really is, rejecting
+    // uploads whose name or type disagree with it
+    let head = content.head();
+    let default_mime = state.config.default_mime_type.to_lowercase();
+    if !matches_detected_type(head, mime_type, &default_mime, &extension) {
+        let detected = detect_mime(head).unwrap_or_default();
+        error!(
+            "Declared type {} (.{}) does not match detected type {}",
+            mime_type, extension, detected
+        );
+        return Err(AppError::UnSupportedMediaType(format!(
+            "File content is {}, which does not match the declared type {} or extension .{}",
+            detected, mime_type, extension
+        )));
+    }
+    let detected_mime = detect_mime(head);
+    let mime_type = detected_mime.as_ref().unwrap_or(mime_type);
+
+    // Reject archives that would expand far beyond their uploaded size
+    // (uploads needing inspection are always buffered, never streamed)
+    if let Some(file_data) = content.buffered().filter(|_| state.config.inspect_archives && extension == "zip") {
+        let stats = inspect_zip(file_data).map_err(|e| {
+            error!("Failed to inspect archive: {}", e);
+            AppError::UnprocessableEntity(format!("Invalid zip archive: {}", e))
+        })?;
+
+        let ratio = stats.uncompressed_size / file_size.max(1);
+        if ratio > state.config.max_archive_ratio
+            || stats.uncompressed_size > state.config.max_archive_uncompressed_size
+        {
+            error!(
+                "Rejected archive with {} entries expanding to {} bytes (ratio {})",
+                stats.entries, stats.uncompressed_size, ratio
+            );
+
+            return Err(AppError::UnprocessableEntity(format!(
+                "Archive expands to {} bytes (ratio {}), exceeding the allowed limits",
+                stats.uncompressed_size, ratio
+            )));
+        }
+    }
+
+    // Generate unique file ID and the stored filename for the configured strategy
     let file_id = Uuid::new_v4();
-    let filename = if let Some(custom_name) = custom_filename {
-        format!("{}_{}", file_id, custom_name)
-    } else {
-        format!("{}.{}", file_id, extension)
-    };
-    let file_path = format!("files/{}", filename);
+    let strategy = state.config.naming_strategy;
+    let requested_name = custom_filename.clone().unwrap_or_else(|| original_filename.clone());
+    let now = chrono::Utc::now();
+    let mut attempt = 0;
+    let mut filename = build_stored_filename(strategy, file_id, &requested_name, &extension, now, attempt);
+    let mut file_path = format!("files/{}", filename);
+    // Collision suffixes are settled by the unique index on insert, so the
+    // object must not reach its live key before the row has claimed the name
+    let collision_suffix = strategy == NamingStrategy::OriginalWithCollisionSuffix;
 
-    // Calculate checksum for deduplication
-    let checksum = calculate_sha256(&file_data);
+    // Optionally dedup images by their pixels rather than their exact bytes;
+    // streamed uploads are never decoded, so they only dedup by bytes
+    let normalized_checksum = match content.buffered() {
+        Some(file_data) if state.config.dedup_normalize_images && is_file_mime_type(mime_type) => {
+            match normalized_image_checksum(file_data, state.config.checksum_algorithm).await {
+                Ok(normalized) => Some(normalized),
+                Err(e) => {
+                    warn!("Failed to normalize image for dedup: {}", e);
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
 
-    // Check if file already exists
-    let existing_file = sqlx::query_as!(
-        File,
-        "SELECT * FROM files WHERE checksum = $1 LIMIT 1",
-        checksum
-    ).fetch_optional(&state.pool)
-    .await?;
+    // Check if file already exists, according to the configured dedup strategy;
+    // a NULL normalized checksum never matches, leaving exact-byte dedup.
+    // Expired files are ignored: they are about to be purged
+    let existing_file = match state.config.dedup_strategy {
+        DedupStrategy::Checksum => sqlx::query_as!(
+            File,
+            r#"
+            SELECT * FROM files
+            WHERE (checksum = $1 OR normalized_checksum = $2)
+              AND (expires_at IS NULL OR expires_at > NOW())
+            LIMIT 1
+            "#,
+            checksum,
+            normalized_checksum
+        ).fetch_optional(&state.pool)
+        .await?,
+        DedupStrategy::ChecksumAndName => sqlx::query_as!(
+            File,
+            r#"
+            SELECT * FROM files
+            WHERE (checksum = $1 OR normalized_checksum = $2)
+              AND original_filename = $3
+              AND (expires_at IS NULL OR expires_at > NOW())
+            LIMIT 1
+            "#,
+            checksum,
+            normalized_checksum,
+            original_filename
+        ).fetch_optional(&state.pool)
+        .await?,
+        DedupStrategy::None => None,
+    };
 
     if let Some(existing) = existing_file {
+        // The shared file must live as long as the longest-lived upload of it:
+        // an upload without a TTL clears the expiry, a later one extends it
+        if existing.expires_at.is_some() {
+            sqlx::query!(
+                r#"
+                UPDATE files
+                SET expires_at = CASE WHEN $2::timestamptz IS NULL THEN NULL ELSE GREATEST(expires_at, $2) END
+                WHERE id = $1
+                "#,
+                existing.id,
+                expires_at
+            )
+            .execute(&state.pool)
+            .await?;
+        }
+
         return Ok(Json(UploadResponse { 
             id: existing.id, 
             filename: existing.filename,
-            url: format!("/files/{}", existing.id), 
+            url: state.config.public_url(&format!("/files/{}", existing.id)), 
             size: existing.file_size, 
             mime_type: existing.mime_type,
-        }));
+        }).into_response());
     }
 
-    // Upload file to storage backend
-    let storage_path = state
-        .storage.upload(&file_path, file_data.clone())
-        .await
-        .map_err(|e| {
-            error!("Error uploading file: {}",e);
-            AppError::InternalServerError("Failed to upload file".into())
-        })?; 
-
-    // Generate and upload thumbnail (if supported MIME type)
-    let thumbnail_path = if is_file_mime_type(&mime_type.clone().unwrap()) {
-        match generate_thumbnail(&file_data, &file_id.to_string()).await {
-            Ok(thumb_path) => match tokio::fs::read(&thumb_path).await {
-                Ok(thumb_data) => {
-                    let thumb_storage_path = format!("thumbnails/{}.jpg", file_id);
-                    if state
-                        .storage
-                        .upload(&thumb_storage_path, Bytes::from(thumb_data))
-                        .await
-                        .is_ok()
-                    {
-                        Some(thumb_storage_path)
-                    } else {
-                        error!("Failed to upload thumbnail");
-                        None
-                    }
+    // Streamed uploads were checked for space before they were written
+    if content.buffered().is_some() {
+        ensure_disk_space(state, file_size).await?;
+    }
+
+    // Fast-fail while storage writes are failing instead of piling up uploads
+    let permit = state
+        .upload_breaker
+        .as_ref()
+        .map(|breaker| breaker.check())
+        .transpose()
+        .map_err(AppError::StorageDegraded)?;
+
+    // With staged uploads the object is written under staging/ and only moved
+    // to its live key once the database row exists
+    let staging_path = (state.config.staged_uploads || collision_suffix)
+        .then(|| format!("{}{}", STAGING_PREFIX, file_id));
+    let write_path = staging_path.as_deref().unwrap_or(&file_path);
+
+    // Upload file to storage backend while the thumbnail (if any) is generated;
+    // the two are independent, so upload latency doesn't include both
+    // (streamed uploads are just moved out of staging, with no thumbnail)
+    let upload_task = async {
+        let started = Instant::now();
+        let uploaded = match content {
+            UploadContent::Buffered(data) => state.storage.upload(write_path, data.clone()).await.map(|_| ()),
+            UploadContent::Streamed { path, .. } => state.storage.rename(path, write_path).await,
+        };
+        (uploaded, started.elapsed())
+    };
+    let thumbnail_task = async {
+        match content.buffered() {
+            Some(file_data) if is_file_mime_type(mime_type) => {
+                let sizes = ThumbnailSize::ALL.map(|size| state.config.thumbnail_dimensions(size));
+                Some(generate_thumbnail(
+                    file_data,
+                    state.config.thumbnail_format,
+                    &sizes,
+                ).await)
+            }
+            _ => None,
+        }
+    };
+    let ((uploaded, upload_latency), generated_thumbnail) = tokio::join!(upload_task, thumbnail_task);
+    if let Some(permit) = permit {
+        permit.record(uploaded.is_ok(), upload_latency);
+    }
+    uploaded.map_err(|e| {
+        error!("Error uploading file: {}",e);
+        AppError::InternalServerError("Failed to upload file".into())
+    })?;
+
+    // Store a gzip variant next to compressible files (staged along with the
+    // file itself); best-effort, since the original can always be served instead
+    let mut gzip_precompressed = false;
+    if let Some(file_data) = content
+        .buffered()
+        .filter(|_| state.config.precompress_uploads && is_compressible(mime_type))
+    {
+        match gzip(file_data).await {
+            Ok(compressed) if compressed.len() < file_data.len() => {
+                let gzip_path = format!("{}.gz", write_path);
+                match state.storage.upload(&gzip_path, Bytes::from(compressed)).await {
+                    Ok(_) => gzip_precompressed = true,
+                    Err(e) => error!("Failed to store gzip variant {}: {}", gzip_path, e),
                 }
-                Err(e) => {
-                    error!("Failed to read thumbnail file: {}", e);
-                    None
+            }
+            Ok(_) => {}
+            Err(e) => error!("Failed to gzip upload {}: {}", file_id, e),
+        }
+    }
+
+    // Upload the thumbnails generated above (if supported MIME type). The medium
+    // size keeps the original `thumbnails/{id}.{ext}` key and is the primary thumbnail.
+    // Failures don't fail the upload but are recorded in thumbnail_status for retry.
+    let mut thumbnail_keys = serde_json::Map::new();
+    match generated_thumbnail {
+        Some(Ok(thumbnails)) => {
+            for (size, thumb_data) in ThumbnailSize::ALL.into_iter().zip(thumbnails) {
+                let extension = state.config.thumbnail_format.extension();
+                let thumb_storage_path = match size {
+                    ThumbnailSize::Medium => format!("thumbnails/{}.{}", file_id, extension),
+                    _ => format!("thumbnails/{}_{}.{}", file_id, size.as_str(), extension),
+                };
+                if state
+                    .storage
+                    .upload(&thumb_storage_path, Bytes::from(thumb_data))
+                    .await
+                    .is_ok()
+                {
+                    thumbnail_keys.insert(size.as_str().to_string(), thumb_storage_path.into());
+                } else {
+                    error!("Failed to upload {} thumbnail", size.as_str());
                 }
-            },
+            }
+        }
+        Some(Err(e)) => error!("Failed to generate thumbnail: {}", e),
+        None => {}
+    }
+
+    let thumbnail_path = thumbnail_keys
+        .get(ThumbnailSize::Medium.as_str())
+        .or_else(|| thumbnail_keys.values().next())
+        .and_then(|key| key.as_str())
+        .map(str::to_string);
+    let thumbnails = (!thumbnail_keys.is_empty()).then(|| serde_json::Value::Object(thumbnail_keys));
+
+    let thumbnail_attempted = content.buffered().is_some() && is_file_mime_type(mime_type);
+    let thumbnail_status = match (&thumbnail_path, thumbnail_attempted) {
+        (Some(_), _) => ThumbnailStatus::Ready,
+        (None, true) => ThumbnailStatus::Failed,
+        (None, false) => ThumbnailStatus::None,
+    };
+
+    // Extract audio metadata (duration, bitrate, tags) for buffered audio files
+    let audio = if let Some(file_data) = content.buffered().filter(|_| mime_type.starts_with("audio/")) {
+        match extract_audio_metadata(file_data) {
+            Ok(metadata) => metadata,
             Err(e) => {
-                error!("Failed to generate thumbnail: {}", e);
-                None
+                error!("Failed to extract audio metadata: {}", e);
+                AudioMetadata::default()
             }
         }
     } else {
-        None
+        AudioMetadata::default()
     };
 
-    // Persist file metadata to database
-    let file_record = sqlx::query_as!(
-        File,
-        r#"
-        INSERT INTO files (
-            id, filename, original_filename, file_path, file_size, mime_type,
-            storage_type, checksum, thumbnail_path
-        ) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9)
-        RETURNING *
-        "#,
-        file_id,
-        filename,
-        original_filename,
-        storage_path,
-        file_size as i64,
-        mime_type.unwrap_or_else(|| "application/octet-stream".into()),
-        if state.config.use_s3 { "s3" } else { "local" },
-        Some(checksum),
-        thumbnail_path
-    )
-    .fetch_one(&state.pool)
-    .await?;
+    // Persist file metadata to database, taking the next collision suffix
+    // while the name is already in use
+    let file_record = loop {
+        let storage_path = state.storage.stored_path(&file_path);
+        let inserted = sqlx::query_as!(
+            File,
+            r#"
+            INSERT INTO files (
+                id, filename, original_filename, file_path, file_size, mime_type,
+                storage_type, checksum, thumbnail_path, audio_duration_ms,
+                audio_bitrate_kbps, audio_title, audio_artist, audio_album, thumbnail_status,
+                flagged, normalized_checksum, thumbnails, expires_at, gzip_precompressed, md5
+            ) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14,$15,$16,$17,$18,$19,$20,$21)
+            RETURNING *
+            "#,
+            file_id,
+            filename,
+            original_filename,
+            storage_path,
+            file_size as i64,
+            mime_type,
+            state.config.storage_backend.as_str(),
+            Some(checksum.as_str()),
+            thumbnail_path,
+            audio.duration_ms,
+            audio.bitrate_kbps,
+            audio.title,
+            audio.artist,
+            audio.album,
+            thumbnail_status.as_column(),
+            flagged,
+            normalized_checksum,
+            thumbnails,
+            expires_at,
+            gzip_precompressed,
+            Some(md5.as_str())
+        )
+        .fetch_one(&state.pool)
+        .await;
+
+        match inserted {
+            Err(sqlx::Error::Database(e)) if collision_suffix && e.is_unique_violation() => {
+                attempt += 1;
+                filename = build_stored_filename(strategy, file_id, &requested_name, &extension, now, attempt);
+                file_path = format!("files/{}", filename);
+            }
+            inserted => break inserted,
+        }
+    };
+
+    let mut file_record = match file_record {
+        Ok(record) => record,
+        Err(e) => {
+            // Don't leave staged objects behind for a row that was never written
+            if let Some(staging_path) = &staging_path {
+                let _ = state.storage.delete(staging_path).await;
+                if gzip_precompressed {
+                    let _ = state.storage.delete(&format!("{}.gz", staging_path)).await;
+                }
+            }
+            return Err(e.into());
+        }
+    };
+
+    // Commit the staged objects to their live keys
+    if let Some(staging_path) = &staging_path {
+        if let Err(e) = state.storage.rename(staging_path, &file_path).await {
+            error!("Failed to commit staged upload {}: {}", staging_path, e);
+            let _ = state.storage.delete(staging_path).await;
+            if gzip_precompressed {
+                let _ = state.storage.delete(&format!("{}.gz", staging_path)).await;
+            }
+            sqlx::query!("DELETE FROM files WHERE id = $1", file_id)
+                .execute(&state.pool)
+                .await?;
+            return Err(AppError::InternalServerError("Failed to upload file".into()));
+        }
+
+        if gzip_precompressed {
+            let staged_gzip = format!("{}.gz", staging_path);
+            if let Err(e) = state.storage.rename(&staged_gzip, &format!("{}.gz", file_path)).await {
+                // The file itself is committed; just stop advertising the variant
+                error!("Failed to commit staged gzip variant {}: {}", staged_gzip, e);
+                let _ = state.storage.delete(&staged_gzip).await;
+                if let Err(e) = sqlx::query!("UPDATE files SET gzip_precompressed = FALSE WHERE id = $1", file_id)
+                    .execute(&state.pool)
+                    .await
+                {
+                    error!("Failed to clear gzip flag of {}: {}", file_id, e);
+                }
+                file_record.gzip_precompressed = false;
+            }
+        }
+    }
 
     info!("File uploaded: {} ({} bytes)", file_id, file_size);
 
-    Ok(Json(UploadResponse { 
-        id: file_id, 
-        filename: file_record.filename, 
-        url: format!("/files/{}", file_id), 
-        size: file_record.file_size, 
-        mime_type: file_record.mime_type,
-    }))
+    let url = state.config.public_url(&format!("/files/{}", file_id));
+
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, url.clone())],
+        Json(UploadResponse { 
+            id: file_id, 
+            filename: file_record.filename, 
+            url, 
+            size: file_record.file_size, 
+            mime_type: file_record.mime_type,
+        }),
+    ).into_response())
 }
 
 /// Download a file by its unique ID.
 pub async fn download_file(
     State(state): State<AppState>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
     Path(id): Path<Uuid>,
+    Query(query): Query<DownloadQuery>,
+    headers: HeaderMap,
 ) -> Result<Response, AppError> {
 
+    // Reject bad parameters before doing any work
+    let target_charset = parse_download_query(&query)?;
+
     // Fetch file metadata from database
     let file = sqlx::query_as!(
         File,
@@ -200,85 +869,79 @@ pub async fn download_file(
     .fetch_optional(&state.pool)
     .await?
     .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
+    ensure_retained(&state, &file)?;
+
+    let plan = plan_download(&state, &file, &query, target_charset, &headers)?;
+
+    // Clients holding the current version skip the storage read entirely
+    if let Some(response) = not_modified(&file, plan.etag.as_deref(), &headers) {
+        return Ok(response);
+    }
+
+    // Throttle hot files so one object can't saturate the storage backend
+    let _download_permit = match &state.download_limiter {
+        Some(limiter) => Some(limiter.try_acquire(file.id).ok_or_else(|| {
+            AppError::TooManyRequests("Too many concurrent downloads of this file".to_string())
+        })?),
+        None => None,
+    };
 
     // Normalize storage path based on backend type
     // - S3 paths are stored as: s3://files/uuid.ext
     // - Local paths are stored as: uploads/files/uuid.ext
     // Storage backend expects a relative key/path
-    let file_path = if file.storage_type == "s3" {
-    file.file_path
-        .strip_prefix("s3://") 
-        .unwrap_or(&file.file_path)
-        .to_string()
-    } else {
-    file.file_path
-        .strip_prefix("uploads/")
-        .unwrap_or(&file.file_path)
-        .to_string()
-    };
+    let file_path = storage_relative_path(&file.storage_type, &file.file_path);
+    let object_path = if plan.serve_gzip { format!("{}.gz", file_path) } else { file_path };
 
-    // Download file contents from storage
-    let content = state.storage.download(&file_path).await.map_err(|e| {
-        error!("Error downloading file {}: {}", file_path, e);
-        AppError::InternalServerError("Failed to download file".to_string())
-    })?;
+    // Serve repeat downloads of remote objects from the local cache when possible
+    let cache = state
+        .download_cache
+        .as_ref()
+        .filter(|_| matches!(file.storage_type.as_str(), "s3" | "azure"));
+    let content = read_through_cache(&state, cache, &object_path).await?;
+
+    // Optionally recompute the checksum to detect storage corruption
+    if plan.verify {
+        if let Some(expected) = &file.checksum {
+            let actual = calculate_checksum(&content, ChecksumAlgorithm::of(expected));
+            if &actual != expected {
+                error!(
+                    "Integrity check failed for {}: expected {}, got {}",
+                    file.id, expected, actual
+                );
+                return Err(AppError::InternalServerError(
+                    "Integrity check failed: stored file does not match its checksum".to_string(),
+                ));
+            }
+        }
+    }
+
+    // Optionally transcode text files into the requested charset
+    let content = match plan.target_charset {
+        Some(target) => Bytes::from(transcode_text(&content, &file.mime_type, target)),
+        None => content,
+    };
 
     // Create HTTP response with binary body 
     let mut response = Response::new(content.into());
+    insert_download_headers(&state, &headers, &file, &plan, response.headers_mut());
 
-    // Set Content-Type header so the browser knows the file type
-    response.headers_mut().insert(
-        header::CONTENT_TYPE,
-        header::HeaderValue::from_str(&file.mime_type)
-            .unwrap_or_else(|_| header::HeaderValue::from_static("application/octet-stream")),
-    );
-
-    // Set Content-Disposition header to force download
-    // and preserve the original filename
-    response.headers_mut().insert(
-        header::CONTENT_DISPOSITION,
-        header::HeaderValue::from_str(&format!("attachment; filename=\"{}\"", file.original_filename))
-            .unwrap_or_else(|_| header::HeaderValue::from_static("attachment")),
-    );
+    record_access(&state, file.id, AccessAction::Download, remote_addr);
 
     Ok(response)
 }
 
-/// Get metadata for a single file by its ID.
-pub async fn get_file(
+/// Answer `HEAD /files/{id}/download` with the headers a download with the
+/// same query would carry, without fetching the file's contents from storage.
+pub async fn head_download(
     State(state): State<AppState>,
-    Path(id): Path<Uuid>
-) -> Result<Json<FileResponse>, AppError> {
-
-    // Query the database for the file record
-    let file = sqlx::query_as!(
-        File,
-        "SELECT * FROM files WHERE id = $1",
-        id
-    )
-    .fetch_optional(&state.pool)
-    .await?
-    .ok_or_else(||AppError::NotFound("File not found".to_string()))?;
-
-    Ok(Json(FileResponse { 
-        id: file.id,
-        filename: file.filename, 
-        original_filename: file.original_filename, 
-        size: file.file_size, 
-        mime_type: file.mime_type, 
-        uploaded_at: file.uploaded_at, 
-        download_url: format!("/files/{}/download", file.id), 
-        thumbnail_url: file.thumbnail_path.map(|_| format!("/files/{}/thumbnail", file.id)),
-    }))
-}
+    Path(id): Path<Uuid>,
+    Query(query): Query<DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
 
-/// Delete a file and its associated resources.
-pub async fn delete_file(
-    State(state): State<AppState>,
-    Path(id): Path<Uuid>
-) -> Result<StatusCode, AppError> {
+    let target_charset = parse_download_query(&query)?;
 
-    // Fetch the file record from the database
     let file = sqlx::query_as!(
         File,
         "SELECT * FROM files WHERE id = $1",
@@ -287,137 +950,1619 @@ pub async fn delete_file(
     .fetch_optional(&state.pool)
     .await?
     .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
+    ensure_retained(&state, &file)?;
 
-    // Resolve the storage-relative file path
-    // (remove "s3://" or "uploads/" prefixes)
-    let file_path = if file.storage_type == "s3" {
-        file.file_path
-            .strip_prefix("s3://") 
-            .unwrap_or(&file.file_path)
-            .to_string()
-    } else {
-        file.file_path
-            .strip_prefix("uploads/")
-            .unwrap_or(&file.file_path)
-            .to_string()
-    };
+    let plan = plan_download(&state, &file, &query, target_charset, &headers)?;
 
-    // Delete the main file from storage
-    state.storage.delete(&file_path).await.map_err(|e| {
-        error!("Failed to delete file {}: {:?}", file_path, e);
-        AppError::InternalServerError("Failed to delete file from storage".to_string())
-    })?;
+    // Answer conditional requests exactly like the matching GET would
+    if let Some(response) = not_modified(&file, plan.etag.as_deref(), &headers) {
+        return Ok(response);
+    }
 
-    // If a thumbnail exists, attempt to delete it as well
-    if let Some(thumb_path) = &file.thumbnail_path {
-        let thumb_relative_path = if file.storage_type == "s3" {
-            thumb_path
-                .strip_prefix("s3://")
-                .unwrap_or(thumb_path)
-                .to_string()
-        } else {
-            thumb_path
-                .strip_prefix("uploads/")
-                .unwrap_or(&file.file_path)
-                .to_string()
-        };
+    // The gzip variant's size comes from storage; a transcoded body's size
+    // isn't known without transcoding it, so it goes unreported
+    let content_length = if plan.serve_gzip {
+        let file_path = storage_relative_path(&file.storage_type, &file.file_path);
+        state.storage.object_size(&format!("{}.gz", file_path)).await.ok().flatten()
+    } else if plan.target_charset.is_none() {
+        Some(file.file_size as u64)
+    } else {
+        None
+    };
 
-        // Thumbnail deletion failure should not block file deletion
-        let _ = state.storage.delete(&thumb_relative_path).await;
+    let mut response = Response::new(Body::empty());
+    insert_download_headers(&state, &headers, &file, &plan, response.headers_mut());
+    if let Some(content_length) = content_length {
+        response.headers_mut().insert(header::CONTENT_LENGTH, header::HeaderValue::from(content_length));
     }
 
-    // Remove the file record from the database
-    sqlx::query!("DELETE FROM files WHERE id = $1", id)
-        .execute(&state.pool)
-        .await?;
-
-    info!("File Deleted: {}", id);
+    Ok(response)
+}
 
-    // 204 No Content indicates successful deletion with no response body
-    Ok(StatusCode::NO_CONTENT)
+/// How a download will be served, worked out once from the file, the query
+/// and the request headers so `GET` and `HEAD` always agree.
+struct DownloadPlan {
+    /// Recompute the checksum before serving.
+    verify: bool,
+    /// Serve the stored gzip variant.
+    serve_gzip: bool,
+    /// Charset to transcode text into.
+    target_charset: Option<&'static Encoding>,
+    /// `Content-Type` of the response body.
+    content_type: String,
+    /// Whether `content_type` comes from a `?content_type=` override.
+    type_overridden: bool,
+    etag: Option<String>,
 }
 
-/// Download and return a file thumbnail.
-pub async fn get_thummbnail(
-    State(state): State<AppState>,
-    Path(id): Path<Uuid>
-) -> Result<Response, AppError> {
+/// Validate a download's query parameters, returning the charset to
+/// transcode into, if any.
+fn parse_download_query(query: &DownloadQuery) -> Result<Option<&'static Encoding>, AppError> {
+    if let Some(content_type) = &query.content_type {
+        content_type.parse::<mime::Mime>().map_err(|_| {
+            AppError::BadRequest(format!("Invalid content_type '{}'", content_type))
+        })?;
+    }
 
-    // Fetch the file record from the database using the file ID
-    let file = sqlx::query_as!(
-        File,
-        "SELECT * FROM files WHERE id = $1",
-        id
-    )
-    .fetch_optional(&state.pool)
-    .await?
-    .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
+    // encoding_rs can decode every WHATWG encoding but can't produce UTF-16 or
+    // replacement output, so only accept targets it encodes as themselves
+    query
+        .charset
+        .as_deref()
+        .map(|label| {
+            Encoding::for_label(label.trim().as_bytes())
+                .filter(|encoding| encoding.output_encoding() == *encoding)
+                .ok_or_else(|| AppError::BadRequest(format!("Unsupported charset '{}'", label)))
+        })
+        .transpose()
+}
 
-    // Ensure the file has an associated thumbnail
-    let thumb_path = file.thumbnail_path.ok_or_else(|| {
-        AppError::NotFound("Thumbnail not available".to_string())
-    })?;
+/// Decide how to serve a download of `file`.
+fn plan_download(
+    state: &AppState,
+    file: &File,
+    query: &DownloadQuery,
+    target_charset: Option<&'static Encoding>,
+    headers: &HeaderMap,
+) -> Result<DownloadPlan, AppError> {
+    if target_charset.is_some() {
+        if !file.mime_type.starts_with("text/") {
+            return Err(AppError::BadRequest(
+                "charset conversion is only supported for text files".to_string(),
+            ));
+        }
+        // Transcoding holds the whole file in memory; plain downloads don't count
+        ensure_inline_size(state, file)?;
+    }
 
-    // Normalize the thumbnail path for the storage backend
-    // Removes prefixes like "s3://" or "uploads/"
-    let thumb_storage_path = if file.storage_type == "s3" {
-        thumb_path
-            .strip_prefix("s3://")
-            .unwrap_or(&thumb_path)
-            .to_string()
-    } else {
-        thumb_path
-            .strip_prefix("uploads/")
-            .unwrap_or(&thumb_path)
-            .to_string()
-    };
+    // Serve the stored gzip variant to clients that accept it, unless the
+    // response needs the original bytes (verification or transcoding)
+    let verify = query.verify.unwrap_or(state.config.verify_downloads);
+    let serve_gzip = !verify && target_charset.is_none() && wants_gzip(file, headers);
 
-    // Download the thumbnail bytes from storage
-    let content = state.storage.download(&thumb_storage_path).await.map_err(|_|
-        AppError::InternalServerError("Failed to download thumbnail".to_string())
-    )?;
+    // An explicit override wins without touching the stored metadata;
+    // transcoded text is labelled with its new charset
+    let mut content_type = query.content_type.clone().unwrap_or_else(|| file.mime_type.clone());
+    if let Some(target) = target_charset {
+        let essence = content_type.split(';').next().unwrap_or("").trim().to_string();
+        content_type = format!("{}; charset={}", essence, target.name().to_lowercase());
+    }
+
+    Ok(DownloadPlan {
+        verify,
+        serve_gzip,
+        target_charset,
+        content_type,
+        type_overridden: query.content_type.is_some(),
+        etag: download_etag(file, serve_gzip, target_charset),
+    })
+}
+
+/// `304 Not Modified` when the request's `If-None-Match` matches `etag`.
+fn not_modified(file: &File, etag: Option<&str>, headers: &HeaderMap) -> Option<Response> {
+    let etag = etag?;
+    let if_none_match = headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok())?;
+    if !etag_matches(if_none_match, etag) {
+        return None;
+    }
+
+    let mut response = (StatusCode::NOT_MODIFIED, [(header::ETAG, etag.to_string())]).into_response();
+    if file.gzip_precompressed {
+        response.headers_mut().insert(header::VARY, header::HeaderValue::from_static("accept-encoding"));
+    }
+    Some(response)
+}
+
+/// Headers shared by `GET` and `HEAD` downloads (everything but the length).
+fn insert_download_headers(
+    state: &AppState,
+    headers: &HeaderMap,
+    file: &File,
+    plan: &DownloadPlan,
+    response_headers: &mut HeaderMap,
+) {
+    // Set Content-Type header so the browser knows the file type
+    response_headers.insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_str(&plan.content_type)
+            .unwrap_or_else(|_| header::HeaderValue::from_static("application/octet-stream")),
+    );
+
+    // Responses differ by Accept-Encoding whenever a gzip variant exists
+    if file.gzip_precompressed {
+        response_headers.insert(header::VARY, header::HeaderValue::from_static("accept-encoding"));
+    }
+    if plan.serve_gzip {
+        response_headers.insert(header::CONTENT_ENCODING, header::HeaderValue::from_static("gzip"));
+    }
+    if let Some(etag) = plan.etag.as_deref().and_then(|etag| header::HeaderValue::from_str(etag).ok()) {
+        response_headers.insert(header::ETAG, etag);
+    }
+
+    response_headers.insert(
+        header::CONTENT_DISPOSITION,
+        content_disposition(state, headers, file, plan.type_overridden),
+    );
+    response_headers.insert(header::X_CONTENT_TYPE_OPTIONS, header::HeaderValue::from_static("nosniff"));
+}
+
+/// Serve an object under `Config::public_prefix` by its storage key, without
+/// a database lookup, for hosting static assets. 404 when disabled.
+pub async fn serve_public(
+    State(state): State<AppState>,
+    Path(path): Path<String>,
+) -> Result<Response, AppError> {
+
+    let prefix = state
+        .config
+        .public_prefix
+        .as_deref()
+        .ok_or_else(|| AppError::NotFound("Not found".to_string()))?;
+
+    if !is_plain_relative_path(&path) {
+        return Err(AppError::BadRequest("Invalid path".to_string()));
+    }
+
+    let key = format!("{}{}", prefix, path);
+    let content = state
+        .storage
+        .download(&key)
+        .await
+        .map_err(|e| download_error(&key, e, "file"))?;
+
+    let content_type = mime_guess::from_path(&path).first_or_octet_stream();
+
+    Ok(([(header::CONTENT_TYPE, content_type.to_string())], content).into_response())
+}
+
+/// Whether `path` is a plain relative path, safe to join onto a key prefix:
+/// no empty, `.` or `..` segments and no backslashes, so nothing outside the
+/// prefix is reachable.
+fn is_plain_relative_path(path: &str) -> bool {
+    !path.contains('\\')
+        && path
+            .split('/')
+            .all(|segment| !segment.is_empty() && segment != "." && segment != "..")
+}
+
+/// Strong ETag for a download, built from the stored checksum. The gzip
+/// variant and transcoded text are different bytes, so they get their own tags.
+/// `None` for files uploaded before checksums were recorded.
+fn download_etag(file: &File, gzip: bool, charset: Option<&'static Encoding>) -> Option<String> {
+    let checksum = file.checksum.as_deref()?;
+    Some(match (gzip, charset) {
+        (true, _) => format!("\"{}-gzip\"", checksum),
+        (false, Some(charset)) => format!("\"{}-{}\"", checksum, charset.name().to_lowercase()),
+        (false, None) => format!("\"{}\"", checksum),
+    })
+}
+
+/// Whether a download of `file` can be served from its gzip variant.
+fn wants_gzip(file: &File, headers: &HeaderMap) -> bool {
+    file.gzip_precompressed
+        && headers
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| accepts_encoding(v, "gzip"))
+}
+
+/// `Content-Disposition` for a download, preserving the original filename.
+/// Browsers opening a previewable file directly get it inline when smart
+/// disposition is enabled; everything else is forced to download. A
+/// `?content_type=` override always downloads, since it could relabel a
+/// stored file as HTML and have it rendered on our origin.
+fn content_disposition(state: &AppState, headers: &HeaderMap, file: &File, type_overridden: bool) -> header::HeaderValue {
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    let disposition = if state.config.smart_disposition
+        && !type_overridden
+        && accept.contains("text/html")
+        && is_inline_previewable(&file.mime_type)
+    {
+        "inline"
+    } else {
+        "attachment"
+    };
+
+    header::HeaderValue::from_str(&format!("{}; filename=\"{}\"", disposition, file.original_filename))
+        .unwrap_or_else(|_| header::HeaderValue::from_static(disposition))
+}
+
+/// Recompute a stored file's checksum and compare it with the recorded one.
+pub async fn verify_file(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<VerifyResponse>, AppError> {
+
+    let file = sqlx::query_as!(
+        File,
+        "SELECT * FROM files WHERE id = $1",
+        id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
+    ensure_retained(&state, &file)?;
+
+    let file_path = storage_relative_path(&file.storage_type, &file.file_path);
+    let content = state
+        .storage
+        .download(&file_path)
+        .await
+        .map_err(|e| download_error(&file_path, e, "file"))?;
+
+    // Recompute with the algorithm the stored checksum was made with
+    let algorithm = file
+        .checksum
+        .as_deref()
+        .map_or(state.config.checksum_algorithm, ChecksumAlgorithm::of);
+    let actual = calculate_checksum(&content, algorithm);
+    let valid = file.checksum.as_deref() == Some(actual.as_str());
+
+    if !valid {
+        error!("Checksum mismatch for {}: expected {:?}, got {}", id, file.checksum, actual);
+    }
+
+    Ok(Json(VerifyResponse { valid, expected: file.checksum, actual }))
+}
+
+/// Get metadata for a single file by its ID.
+/// Responds with an `ETag` and honors `If-None-Match` with `304 Not Modified`.
+pub async fn get_file(
+    State(state): State<AppState>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<FileQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+
+    let tz = parse_tz(query.tz.as_deref())?;
+
+    // Query the database for the file record
+    let file = sqlx::query_as!(
+        File,
+        "SELECT * FROM files WHERE id = $1",
+        id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(||AppError::NotFound("File not found".to_string()))?;
+    ensure_retained(&state, &file)?;
+
+    record_access(&state, file.id, AccessAction::View, remote_addr);
+
+    // Clients polling an unchanged record get a cheap 304
+    let etag = metadata_etag(&file);
+    let if_none_match = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok());
+    if if_none_match.is_some_and(|value| etag_matches(value, &etag)) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+
+    Ok(([(header::ETAG, etag)], Json(file_response(&state, file, tz))).into_response())
+}
+
+/// Return a manifest with the download URL (presigned where possible) and
+/// verification data for a file.
+pub async fn get_manifest(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<DownloadManifestEntry>, AppError> {
+
+    let file = sqlx::query_as!(
+        File,
+        "SELECT * FROM files WHERE id = $1",
+        id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
+    ensure_retained(&state, &file)?;
+
+    Ok(Json(manifest_entry(&state, file, chrono::Utc::now()).await?))
+}
+
+/// Manifest of a file, pointing at a presigned storage URL when the backend
+/// supports one and at `GET /files/{id}/download` otherwise.
+async fn manifest_entry(
+    state: &AppState,
+    file: File,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<DownloadManifestEntry, AppError> {
+    let file_path = storage_relative_path(&file.storage_type, &file.file_path);
+    let expiry = Duration::from_secs(state.config.presigned_url_expiry_secs);
+    let presigned = state.storage.presigned_url(&file_path, expiry).await.map_err(|e| {
+        error!("Failed to presign {}: {}", file_path, e);
+        AppError::InternalServerError("Failed to presign download URL".to_string())
+    })?;
+    let (download_url, expires_at) = match presigned {
+        Some(url) => (url, Some(now + chrono::Duration::seconds(state.config.presigned_url_expiry_secs as i64))),
+        None => (state.config.public_url(&format!("/files/{}/download", file.id)), None),
+    };
+
+    Ok(DownloadManifestEntry {
+        presigned: expires_at.is_some(),
+        expires_at,
+        manifest: FileManifest {
+            id: file.id,
+            etag: download_etag(&file, false, None),
+            filename: file.original_filename,
+            download_url,
+            size: file.file_size,
+            mime_type: file.mime_type,
+            checksum: file.checksum,
+            md5: file.md5,
+        },
+    })
+}
+
+/// Maximum number of files in one bulk download manifest.
+const MAX_MANIFEST_FILES: usize = 1000;
+
+/// Return download URLs (presigned where possible), sizes and checksums for
+/// several files at once, for clients that fetch and verify them in parallel.
+pub async fn download_manifest(
+    State(state): State<AppState>,
+    Json(payload): Json<DownloadManifestRequest>,
+) -> Result<Json<DownloadManifest>, AppError> {
+
+    let mut ids = payload.ids;
+    let mut seen = std::collections::HashSet::new();
+    ids.retain(|id| seen.insert(*id));
+
+    if ids.is_empty() {
+        return Err(AppError::BadRequest("ids must not be empty".to_string()));
+    }
+    if ids.len() > MAX_MANIFEST_FILES {
+        return Err(AppError::BadRequest(format!(
+            "Too many ids (maximum {})",
+            MAX_MANIFEST_FILES
+        )));
+    }
+
+    let mut files: std::collections::HashMap<Uuid, File> = sqlx::query_as!(
+        File,
+        "SELECT * FROM files WHERE id = ANY($1)",
+        &ids
+    )
+    .fetch_all(&state.pool)
+    .await?
+    .into_iter()
+    .map(|file| (file.id, file))
+    .collect();
+
+    let now = chrono::Utc::now();
+    let mut manifest = DownloadManifest { files: Vec::new(), missing: Vec::new() };
+
+    for id in ids {
+        let Some(file) = files
+            .remove(&id)
+            .filter(|file| !file.is_expired(state.config.default_retention_days, now))
+        else {
+            manifest.missing.push(id);
+            continue;
+        };
+
+        manifest.files.push(manifest_entry(&state, file, now).await?);
+    }
+
+    Ok(Json(manifest))
+}
+
+/// Delete a file and its associated resources.
+pub async fn delete_file(
+    State(state): State<AppState>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<DeleteQuery>,
+) -> Result<Response, AppError> {
+
+    // Fetch the file record from the database
+    let file = sqlx::query_as!(
+        File,
+        "SELECT * FROM files WHERE id = $1",
+        id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
+
+    // Preview the deletion without touching storage or the database
+    if query.dry_run.unwrap_or(false) {
+        return Ok(Json(DeletePreview {
+            bytes_reclaimed: file.file_size,
+            files: vec![DeletePreviewItem {
+                id: file.id,
+                storage_objects: storage_objects(&state, &file).await,
+                original_filename: file.original_filename,
+                size: file.file_size,
+            }],
+        }).into_response());
+    }
+
+    delete_stored_objects(&state, &file).await?;
+
+    // Remove the file record from the database
+    sqlx::query!("DELETE FROM files WHERE id = $1", id)
+        .execute(&state.pool)
+        .await?;
+
+    info!("File Deleted: {}", id);
+
+    record_access(&state, id, AccessAction::Delete, remote_addr);
+
+    // 204 No Content indicates successful deletion with no response body
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Number of expired files fetched at a time during a cleanup sweep.
+const CLEANUP_BATCH_SIZE: i64 = 500;
+
+/// Delete files past their `expires_at` or the retention period from storage
+/// and the database. Run periodically from main; returns how many were removed.
+pub async fn purge_expired_files(state: &AppState) -> Result<usize, AppError> {
+    let retention_days = i32::try_from(state.config.default_retention_days).unwrap_or(i32::MAX);
+    let mut removed = 0;
+    // Page through the expired files by id, so rows whose storage deletion
+    // failed are passed over instead of filling every following batch
+    let mut after: Option<Uuid> = None;
+    loop {
+        let expired = sqlx::query_as!(
+            File,
+            r#"
+            SELECT * FROM files
+            WHERE (expires_at <= NOW()
+               OR ($1 > 0 AND uploaded_at <= NOW() - make_interval(days => $1)))
+              AND ($3::uuid IS NULL OR id > $3)
+            ORDER BY id
+            LIMIT $2
+            "#,
+            retention_days,
+            CLEANUP_BATCH_SIZE,
+            after
+        )
+        .fetch_all(&state.pool)
+        .await?;
+
+        let batch_len = expired.len();
+        after = expired.last().map(|file| file.id);
+        for file in expired {
+            // Keep the row when storage fails so the next sweep retries it
+            if delete_stored_objects(state, &file).await.is_err() {
+                continue;
+            }
+            sqlx::query!("DELETE FROM files WHERE id = $1", file.id)
+                .execute(&state.pool)
+                .await?;
+            info!("Expired file deleted: {}", file.id);
+            removed += 1;
+        }
+
+        if (batch_len as i64) < CLEANUP_BATCH_SIZE {
+            break;
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Storage-relative keys of every object stored for a file: the file itself
+/// first, then its gzip variant, thumbnails and cached conversions.
+async fn storage_objects(state: &AppState, file: &File) -> Vec<String> {
+    // Resolve the storage-relative file path
+    // (remove "s3://" or "uploads/" prefixes)
+    let file_path = storage_relative_path(&file.storage_type, &file.file_path);
+    let gzip_path = file.gzip_precompressed.then(|| format!("{}.gz", file_path));
+    let thumb_relative_paths = file
+        .thumbnail_keys()
+        .into_iter()
+        .map(|thumb_path| storage_relative_path(&file.storage_type, &thumb_path));
+
+    // Cached conversions aren't recorded on the row, so find them by prefix
+    let conversions = match state.storage.list(&format!("conversions/{}", file.id)).await {
+        Ok(conversions) => conversions.into_iter().map(|object| object.key).collect(),
+        Err(e) => {
+            warn!("Failed to list conversions of {}: {}", file.id, e);
+            Vec::new()
+        }
+    };
+
+    std::iter::once(file_path)
+        .chain(gzip_path)
+        .chain(thumb_relative_paths)
+        .chain(conversions)
+        .collect()
+}
+
+/// Delete a file's objects, including cached conversions, from storage and
+/// the download cache. Only failing to delete the file itself is an error.
+async fn delete_stored_objects(state: &AppState, file: &File) -> Result<(), AppError> {
+    for (index, object) in storage_objects(state, file).await.into_iter().enumerate() {
+        match state.storage.delete(&object).await {
+            Ok(()) => {}
+            Err(e) if index == 0 => {
+                error!("Failed to delete file {}: {:?}", object, e);
+                return Err(AppError::InternalServerError("Failed to delete file from storage".to_string()));
+            }
+            // Variant, thumbnail and conversion deletion failures should not block file deletion
+            Err(_) => {}
+        }
+
+        if let Some(cache) = &state.download_cache {
+            cache.remove(&object).await;
+        }
+    }
+    Ok(())
+}
+
+/// Download and return a file thumbnail.
+pub async fn get_thummbnail(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<ThumbnailQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+
+    // Fetch the file record from the database using the file ID
+    let file = sqlx::query_as!(
+        File,
+        "SELECT * FROM files WHERE id = $1",
+        id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
+    ensure_retained(&state, &file)?;
+
+    // Pick the requested size (or its nearest rendered neighbour)
+    let thumb_path = match query.size {
+        Some(size) => file.thumbnail_for(size),
+        None => file.thumbnail_path.clone(),
+    };
+
+    // Ensure the file has an associated thumbnail, explaining why when it doesn't
+    let thumb_path = thumb_path.ok_or_else(|| {
+        match file.thumbnail_state() {
+            ThumbnailStatus::Pending => {
+                AppError::NotFound("Thumbnail is still being generated".to_string())
+            }
+            ThumbnailStatus::Failed => {
+                AppError::NotFound("Thumbnail generation failed".to_string())
+            }
+            _ => AppError::NotFound("Thumbnail not available".to_string()),
+        }
+    })?;
+
+    // Normalize the thumbnail path for the storage backend
+    // Removes prefixes like "s3://" or "uploads/"
+    let thumb_storage_path = storage_relative_path(&file.storage_type, &thumb_path);
+
+    // The stored key's extension records the format the thumbnail was generated in
+    let format = ConvertFormat::from_path(&thumb_storage_path).unwrap_or(ConvertFormat::Jpeg);
+
+    // Download the thumbnail bytes from storage
+    let content = state
+        .storage
+        .download(&thumb_storage_path)
+        .await
+        .map_err(|e| download_error(&thumb_storage_path, e, "thumbnail"))?;
+
+    // Watermark the served copy only; the stored thumbnail stays untouched
+    let content = match &state.watermark {
+        Some(watermark) => Bytes::from(
+            apply_watermark(
+                &content,
+                watermark.clone(),
+                state.config.watermark_position,
+                state.config.watermark_opacity,
+                format,
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to watermark thumbnail {}: {}", id, e);
+                AppError::FileProcessingError("Failed to watermark thumbnail".to_string())
+            })?,
+        ),
+        None => content,
+    };
+
+    // Thumbnails have no stored hash, so tag the bytes actually served; a
+    // match still saves sending them
+    let etag = format!("\"{}\"", calculate_sha256(&content));
+    let if_none_match = headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok());
+    if if_none_match.is_some_and(|value| etag_matches(value, &etag)) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
 
     // Create an HTTP response with the binary thumbnail data
     let mut response = Response::new(content.into());
+    if let Ok(etag) = header::HeaderValue::from_str(&etag) {
+        response.headers_mut().insert(header::ETAG, etag);
+    }
+
+    // Explicitly set the content type to the thumbnail's format
+    // This allows browsers and clients to correctly render the image
+    response.headers_mut().insert(
+        header::CONTENT_TYPE, 
+        header::HeaderValue::from_static(format.mime_type())
+    );
+
+    Ok(response)
+}
+
+/// Serve a PNG QR code encoding the file's download URL.
+pub async fn file_qr_code(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<QrQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+
+    let file = sqlx::query_as!(
+        File,
+        "SELECT * FROM files WHERE id = $1",
+        id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
+    ensure_retained(&state, &file)?;
+
+    let size = query.size.unwrap_or(300).clamp(64, 1024);
+    let url = absolute_url(&state, &headers, &format!("/files/{}/download", file.id))?;
+
+    let png = generate_qr_png(&url, size).map_err(|e| {
+        error!("Failed to generate QR code for {}: {}", file.id, e);
+        AppError::InternalServerError("Failed to generate QR code".to_string())
+    })?;
+
+    Ok(([(header::CONTENT_TYPE, "image/png")], png).into_response())
+}
+
+/// Absolute client-facing URL for `path`, for uses like QR codes where there
+/// is no page to resolve a relative one against. A relative `PUBLIC_BASE_URL`
+/// is resolved against the request's `Host` and `X-Forwarded-Proto`.
+fn absolute_url(state: &AppState, headers: &HeaderMap, path: &str) -> Result<String, AppError> {
+    let url = state.config.public_url(path);
+    if url.starts_with("http://") || url.starts_with("https://") {
+        return Ok(url);
+    }
+
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .filter(|host| !host.is_empty())
+        .ok_or_else(|| AppError::BadRequest("A Host header is required to build the file URL".to_string()))?;
+    let scheme = headers
+        .get("x-forwarded-proto")
+        .and_then(|proto| proto.to_str().ok())
+        .and_then(|proto| proto.split(',').next())
+        .map(str::trim)
+        .filter(|proto| *proto == "https" || *proto == "http")
+        .unwrap_or("http");
+
+    Ok(format!("{}://{}{}", scheme, host, url))
+}
+
+/// Return a time-limited URL for fetching a file directly from storage,
+/// falling back to the download endpoint for backends that can't presign.
+pub async fn presigned_url(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<PresignedUrl>, AppError> {
+
+    let file = sqlx::query_as!(
+        File,
+        "SELECT * FROM files WHERE id = $1",
+        id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
+    ensure_retained(&state, &file)?;
+
+    let expiry = Duration::from_secs(state.config.presigned_url_expiry_secs);
+    let file_path = storage_relative_path(&file.storage_type, &file.file_path);
+    let presigned = state.storage.presigned_url(&file_path, expiry).await.map_err(|e| {
+        error!("Failed to presign {}: {}", file_path, e);
+        AppError::InternalServerError("Failed to presign download URL".to_string())
+    })?;
+
+    Ok(Json(match presigned {
+        Some(url) => PresignedUrl {
+            url,
+            expires_at: Some(chrono::Utc::now() + chrono::Duration::seconds(state.config.presigned_url_expiry_secs as i64)),
+            presigned: true,
+        },
+        None => PresignedUrl {
+            url: state.config.public_url(&format!("/files/{}/download", file.id)),
+            expires_at: None,
+            presigned: false,
+        },
+    }))
+}
+
+/// Render a text or source file as syntax-highlighted HTML.
+pub async fn preview_file(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Response, AppError> {
+
+    let file = sqlx::query_as!(
+        File,
+        "SELECT * FROM files WHERE id = $1",
+        id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
+    ensure_retained(&state, &file)?;
+
+    let extension = get_file_extension(&file.original_filename);
+    let syntax = preview_syntax(&file.mime_type, extension.as_deref()).ok_or_else(|| {
+        AppError::UnSupportedMediaType("Only text and source files can be previewed".to_string())
+    })?;
+
+    ensure_inline_size(&state, &file)?;
+
+    let file_path = storage_relative_path(&file.storage_type, &file.file_path);
+    let content = state
+        .storage
+        .download(&file_path)
+        .await
+        .map_err(|e| download_error(&file_path, e, "file"))?;
+
+    let text = String::from_utf8(content.to_vec()).map_err(|_| {
+        AppError::UnSupportedMediaType("File is not valid UTF-8 text".to_string())
+    })?;
+
+    let html = render_preview_html(text, syntax, &file.original_filename).await.map_err(|e| {
+        error!("Failed to render preview of {}: {}", id, e);
+        AppError::FileProcessingError("Failed to render preview".to_string())
+    })?;
+
+    Ok(([(header::CONTENT_TYPE, "text/html; charset=utf-8")], html).into_response())
+}
+
+/// Serve an image re-encoded in another format, e.g. `?format=jpeg&quality=85`.
+/// Converted images are cached in storage under `conversions/`.
+pub async fn convert_file(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<ConvertQuery>,
+) -> Result<Response, AppError> {
+
+    let format = ConvertFormat::parse(&query.format).ok_or_else(|| {
+        AppError::BadRequest(format!(
+            "Unsupported format: {} (expected jpeg, png or webp)",
+            query.format
+        ))
+    })?;
+    let quality = query.quality.unwrap_or(85);
+    if !(1..=100).contains(&quality) {
+        return Err(AppError::BadRequest("quality must be between 1 and 100".to_string()));
+    }
 
-    // Explicitly set the content type to JPEG
-    // This allows browsers and clients to correctly render the image
+    let file = sqlx::query_as!(
+        File,
+        "SELECT * FROM files WHERE id = $1",
+        id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
+    ensure_retained(&state, &file)?;
+
+    if !is_file_mime_type(&file.mime_type) {
+        return Err(AppError::UnSupportedMediaType(
+            "Only images can be converted".to_string(),
+        ));
+    }
+
+    // Quality only affects JPEG output, so only JPEG keys include it
+    let cache_key = match format {
+        ConvertFormat::Jpeg => format!("conversions/{}_q{}.{}", id, quality, format.extension()),
+        _ => format!("conversions/{}.{}", id, format.extension()),
+    };
+
+    let content = match state.storage.download(&cache_key).await {
+        Ok(cached) => cached,
+        Err(_) => {
+            let file_path = storage_relative_path(&file.storage_type, &file.file_path);
+            let original = state
+                .storage
+                .download(&file_path)
+                .await
+                .map_err(|e| download_error(&file_path, e, "file"))?;
+
+            let converted = Bytes::from(convert_image(&original, format, quality).await.map_err(|e| {
+                error!("Failed to convert image {}: {}", id, e);
+                AppError::FileProcessingError("Failed to convert image".to_string())
+            })?);
+
+            // Caching is best-effort; a failed write only costs a re-conversion later
+            if let Err(e) = state.storage.upload(&cache_key, converted.clone()).await {
+                error!("Failed to cache converted image {}: {}", cache_key, e);
+            }
+
+            converted
+        }
+    };
+
+    let mut response = Response::new(content.into());
     response.headers_mut().insert(
-        header::CONTENT_TYPE, 
-        header::HeaderValue::from_static("image/jpeg")
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static(format.mime_type()),
     );
 
     Ok(response)
 }
 
-/// List recently uploaded files.
+/// List recently uploaded files, with optional filters and pagination.
 pub async fn list_files(
-    State(state): State<AppState>
-) -> Result<Json<Vec<FileResponse>>, AppError> {
+    State(state): State<AppState>,
+    Query(query): Query<ListQuery>,
+) -> Result<Response, AppError> {
+
+    let tz = parse_tz(query.tz.as_deref())?;
+    let fields = parse_fields(query.fields.as_deref(), state.config.strict_fields)?;
+
+    // Fetch the requested page of file records, newest first unless sorted otherwise
+    let files = select_files(&query, state.config.default_sort_direction, state.config.default_retention_days)?
+        .build_query_as::<File>()
+        .fetch_all(&state.pool)
+        .await?;
+
+    // Count all matching records so pagination links can point at the last page
+    let total: i64 = count_files(&query, state.config.default_retention_days)
+        .build_query_scalar()
+        .fetch_one(&state.pool)
+        .await?;
+    let link = link_header(&state.config.public_url("/files"), &query, total)?;
 
-    // Fetch the most recent 100 file records from the database
-    let files = sqlx::query_as!(
+    // Transform database File models into FileResponse objects
+    // suitable for API output
+    let response: Vec<FileResponse> = files.into_iter().map(|file| file_response(&state, file, tz)).collect();
+
+    // Return the list as a JSON array, with RFC 5988 pagination links;
+    // a sparse fieldset keeps only the requested keys of each item
+    let mut response = match fields {
+        Some(fields) => {
+            let projected: Vec<serde_json::Value> = response
+                .iter()
+                .map(|item| project_fields(item, &fields))
+                .collect::<Result<_, _>>()
+                .map_err(|e| AppError::InternalServerError(format!("Failed to serialize files: {}", e)))?;
+            Json(projected).into_response()
+        }
+        None => Json(response).into_response(),
+    };
+    if let Ok(value) = header::HeaderValue::from_str(&link) {
+        response.headers_mut().insert(header::LINK, value);
+    }
+    response
+        .headers_mut()
+        .insert("x-total-count", header::HeaderValue::from(total));
+
+    Ok(response)
+}
+
+/// Stream every file matching the filters as newline-delimited JSON,
+/// reading rows through a database cursor instead of paginating.
+pub async fn stream_files(
+    State(state): State<AppState>,
+    Query(query): Query<ListQuery>,
+) -> Result<Response, AppError> {
+    let tz = parse_tz(query.tz.as_deref())?;
+    let mut builder = select_all_files(&query, state.config.default_sort_direction, state.config.default_retention_days)?;
+    let (mut tx, rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(64);
+
+    tokio::spawn(async move {
+        let mut rows = builder.build_query_as::<File>().fetch(&state.pool);
+
+        loop {
+            let line = match rows.try_next().await {
+                Ok(Some(file)) => match serde_json::to_vec(&file_response(&state, file, tz)) {
+                    Ok(mut line) => {
+                        line.push(b'\n');
+                        Ok(Bytes::from(line))
+                    }
+                    Err(e) => Err(std::io::Error::other(e)),
+                },
+                Ok(None) => break,
+                Err(e) => {
+                    error!("Failed to stream files: {}", e);
+                    Err(std::io::Error::other(e))
+                }
+            };
+
+            // Errors abort the body so clients can tell the export is incomplete
+            let is_err = line.is_err();
+            if tx.send(line).await.is_err() || is_err {
+                break;
+            }
+        }
+    });
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(rx),
+    )
+        .into_response())
+}
+
+/// Enable or disable maintenance mode at runtime.
+pub async fn set_maintenance(
+    State(state): State<AppState>,
+    Json(payload): Json<MaintenanceRequest>,
+) -> Json<MaintenanceResponse> {
+    state.maintenance.store(payload.enabled, Ordering::Relaxed);
+
+    info!("Maintenance mode {}", if payload.enabled { "enabled" } else { "disabled" });
+
+    Json(MaintenanceResponse { maintenance: payload.enabled })
+}
+
+/// Compute and store checksums and MD5s for files that were uploaded without them.
+pub async fn backfill_checksums(
+    State(state): State<AppState>,
+) -> Result<Json<BackfillResponse>, AppError> {
+    let mut report = BackfillResponse { scanned: 0, updated: 0, failed: 0 };
+
+    // Stream rows instead of loading every file record into memory at once
+    let mut files = sqlx::query_as!(
         File,
-        "SELECT * FROM files ORDER BY uploaded_at DESC LIMIT 100",
+        "SELECT * FROM files WHERE checksum IS NULL OR md5 IS NULL"
+    )
+    .fetch(&state.pool);
+
+    while let Some(file) = files.try_next().await? {
+        report.scanned += 1;
+
+        let file_path = storage_relative_path(&file.storage_type, &file.file_path);
+        let content = match state.storage.download(&file_path).await {
+            Ok(content) => content,
+            Err(e) => {
+                error!("Backfill: failed to download {}: {}", file_path, e);
+                report.failed += 1;
+                continue;
+            }
+        };
+
+        // A recorded checksum is kept, whatever algorithm it was made with
+        let digests = ContentDigests::of(&content, state.config.checksum_algorithm);
+        sqlx::query!(
+            "UPDATE files SET checksum = COALESCE(checksum, $1), md5 = $2 WHERE id = $3",
+            digests.checksum,
+            digests.md5,
+            file.id
+        )
+        .execute(&state.pool)
+        .await?;
+
+        report.updated += 1;
+    }
+
+    info!(
+        "Checksum backfill finished: {} scanned, {} updated, {} failed",
+        report.scanned, report.updated, report.failed
+    );
+
+    Ok(Json(report))
+}
+
+/// Return the most recent log lines captured in memory.
+pub async fn recent_logs(
+    State(state): State<AppState>,
+    Query(query): Query<RecentLogsQuery>,
+) -> Json<RecentLogsResponse> {
+    let limit = query.lines.unwrap_or(100);
+    Json(RecentLogsResponse { lines: state.logs.recent(limit) })
+}
+
+/// Report bytes and file counts grouped by storage backend.
+/// Backend-reported usage is left to the admin route, since it may list a whole bucket.
+pub async fn public_storage_stats(State(state): State<AppState>) -> Result<Json<StorageStatsResponse>, AppError> {
+    storage_usage(&state, false).await.map(Json)
+}
+
+/// Report bytes and file counts grouped by storage backend, optionally with
+/// the active backend's own usage figure.
+pub async fn storage_stats(
+    State(state): State<AppState>,
+    Query(query): Query<StorageStatsQuery>,
+) -> Result<Json<StorageStatsResponse>, AppError> {
+    storage_usage(&state, query.include_backend).await.map(Json)
+}
+
+async fn storage_usage(state: &AppState, include_backend: bool) -> Result<StorageStatsResponse, AppError> {
+    let backends = sqlx::query_as!(
+        BackendUsage,
+        r#"
+        SELECT
+            storage_type,
+            COUNT(*) AS "file_count!",
+            COALESCE(SUM(file_size), 0)::BIGINT AS "total_bytes!"
+        FROM files
+        GROUP BY storage_type
+        ORDER BY storage_type
+        "#
     )
     .fetch_all(&state.pool)
     .await?;
 
-    // Transform database File models into FileResponse objects
-    // suitable for API output
-    let response = files.into_iter().map(|file| {
-        FileResponse {
-            id: file.id,
-            filename: file.filename,
-            original_filename: file.original_filename,
-            size: file.file_size,
-            mime_type: file.mime_type,
-            uploaded_at: file.uploaded_at,
-            download_url: format!("/files/{}/download", file.id),
-            thumbnail_url: file.thumbnail_path.map(|_| format!("files/{}/thumbnail", file.id))
+    // Backend-reported usage is optional since it may require listing the whole bucket
+    let backend_reported_bytes = if include_backend {
+        state.storage.usage().await.map_err(|e| {
+            error!("Failed to read storage usage: {}", e);
+            AppError::InternalServerError("Failed to read storage usage".to_string())
+        })?
+    } else {
+        None
+    };
+
+    Ok(StorageStatsResponse { backends, backend_reported_bytes })
+}
+
+/// List files whose storage object is missing, probing storage row by row.
+/// Stops once `limit` broken files (default 100) have been found.
+pub async fn broken_files(
+    State(state): State<AppState>,
+    Query(query): Query<BrokenFilesQuery>,
+) -> Result<Json<BrokenFilesResponse>, AppError> {
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    let mut report = BrokenFilesResponse { scanned: 0, broken: Vec::new() };
+
+    let mut files = sqlx::query_as!(
+        File,
+        "SELECT * FROM files ORDER BY uploaded_at DESC"
+    )
+    .fetch(&state.pool);
+
+    while let Some(file) = files.try_next().await? {
+        report.scanned += 1;
+
+        let file_path = storage_relative_path(&file.storage_type, &file.file_path);
+        match state.storage.exists(&file_path).await {
+            Ok(true) => {}
+            Ok(false) => {
+                report.broken.push(BrokenFile {
+                    id: file.id,
+                    original_filename: file.original_filename,
+                    file_path,
+                });
+                if report.broken.len() >= limit {
+                    break;
+                }
+            }
+            Err(e) => error!("Failed to check storage for {}: {}", file_path, e),
+        }
+    }
+
+    info!("Broken file scan: {} scanned, {} broken", report.scanned, report.broken.len());
+
+    Ok(Json(report))
+}
+
+/// Show where a file's object lives in storage and whether it's still there.
+pub async fn file_storage_details(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<StorageDetails>, AppError> {
+    let file = sqlx::query_as!(
+        File,
+        "SELECT * FROM files WHERE id = $1",
+        id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
+
+    let file_path = storage_relative_path(&file.storage_type, &file.file_path);
+    let stored_size = state.storage.object_size(&file_path).await.map_err(|e| {
+        error!("Failed to inspect {} in storage: {}", file_path, e);
+        AppError::InternalServerError("Failed to inspect storage".to_string())
+    })?;
+
+    Ok(Json(StorageDetails {
+        id: file.id,
+        location: state.storage.location(),
+        key: state.storage.object_key(&file_path),
+        exists: stored_size.is_some(),
+        stored_size,
+        recorded_size: file.file_size,
+        thumbnail_key: file.thumbnail_path.as_deref().map(|thumb_path| {
+            state.storage.object_key(&storage_relative_path(&file.storage_type, thumb_path))
+        }),
+        storage_type: file.storage_type,
+    }))
+}
+
+/// Report download cache size, entry count and hit rate.
+pub async fn cache_stats(State(state): State<AppState>) -> Result<Json<CacheStats>, AppError> {
+    let cache = state.download_cache.as_ref()
+        .ok_or_else(|| AppError::NotFound("Download cache is disabled".to_string()))?;
+
+    Ok(Json(cache.stats()))
+}
+
+/// Drop every entry from the download cache.
+pub async fn purge_cache(State(state): State<AppState>) -> Result<Json<CachePurgeResponse>, AppError> {
+    let cache = state.download_cache.as_ref()
+        .ok_or_else(|| AppError::NotFound("Download cache is disabled".to_string()))?;
+
+    let purged = cache.purge().await;
+    info!("Purged {} entries from the download cache", purged);
+
+    Ok(Json(CachePurgeResponse { purged }))
+}
+
+/// Record a file access in the audit log from a background task so the
+/// insert never delays (or fails) the request itself.
+fn record_access(state: &AppState, file_id: Uuid, action: AccessAction, remote_addr: SocketAddr) {
+    let pool = state.pool.clone();
+    tokio::spawn(async move {
+        let result = sqlx::query!(
+            "INSERT INTO access_log (file_id, action, requester_ip) VALUES ($1, $2, $3)",
+            file_id,
+            action.as_str(),
+            remote_addr.ip().to_string()
+        )
+        .execute(&pool)
+        .await;
+
+        if let Err(e) = result {
+            error!("Failed to record {} of {}: {}", action.as_str(), file_id, e);
+        }
+    });
+}
+
+/// Page through the access log of a single file, most recent first.
+pub async fn file_access_log(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<AccessLogQuery>,
+) -> Result<Json<Vec<AccessLogEntry>>, AppError> {
+    let (limit, offset) = page(&ListQuery {
+        limit: query.limit,
+        offset: query.offset,
+        ..Default::default()
+    })?;
+
+    let entries = sqlx::query_as!(
+        AccessLogEntry,
+        r#"
+        SELECT * FROM access_log
+        WHERE file_id = $1
+        ORDER BY accessed_at DESC, id DESC
+        LIMIT $2 OFFSET $3
+        "#,
+        id,
+        limit,
+        offset
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    Ok(Json(entries))
+}
+
+/// Build the API representation of a file record.
+/// `uploaded_at_local` is filled in when a timezone was requested.
+fn file_response(state: &AppState, file: File, tz: Option<Tz>) -> FileResponse {
+    FileResponse {
+        id: file.id,
+        audio: file.audio_metadata(),
+        thumbnail_status: file.thumbnail_state(),
+        flagged: file.flagged,
+        filename: file.filename,
+        original_filename: file.original_filename,
+        size: file.file_size,
+        category: mime_category(&file.mime_type),
+        mime_type: file.mime_type,
+        uploaded_at_epoch_ms: file.uploaded_at.map(|t| t.timestamp_millis()),
+        uploaded_at_local: file.uploaded_at.zip(tz).map(|(t, tz)| t.with_timezone(&tz).fixed_offset()),
+        uploaded_at: file.uploaded_at,
+        updated_at: file.updated_at,
+        download_url: state.config.public_url(&format!("/files/{}/download", file.id)),
+        thumbnail_url: file.thumbnail_path.map(|_| state.config.public_url(&format!("/files/{}/thumbnail", file.id))),
+    }
+}
+
+/// Map a failed storage download of `what` ("file" or "thumbnail") to an
+/// error response. A missing object means the database row outlived it, so
+/// clients get a 404 rather than a retryable 500.
+fn download_error(path: &str, e: StorageError, what: &str) -> AppError {
+    match e {
+        StorageError::NotFound(_) => {
+            warn!("{} {} is recorded but missing from storage", what, path);
+            AppError::NotFound(format!("Stored {} is missing", what))
+        }
+        e => {
+            error!("Error downloading {} {}: {}", what, path, e);
+            AppError::InternalServerError(format!("Failed to download {}", what))
+        }
+    }
+}
+
+/// Download an object, serving it from `cache` when it holds a live copy and
+/// filling the cache on a miss.
+async fn read_through_cache(state: &AppState, cache: Option<&DownloadCache>, object_path: &str) -> Result<Bytes, AppError> {
+    let cached = match cache {
+        Some(cache) => cache.get(object_path).await,
+        None => None,
+    };
+    if let Some(content) = cached {
+        return Ok(content);
+    }
+
+    // Download file contents from storage
+    let content = state
+        .storage
+        .download(object_path)
+        .await
+        .map_err(|e| download_error(object_path, e, "file"))?;
+    if let Some(cache) = cache {
+        cache.insert(object_path, &content).await;
+    }
+    Ok(content)
+}
+
+/// Treat a file past its expiry or retention as gone, even before the cleanup
+/// task has removed it.
+fn ensure_retained(state: &AppState, file: &File) -> Result<(), AppError> {
+    if file.is_expired(state.config.default_retention_days, chrono::Utc::now()) {
+        return Err(AppError::Gone("File has expired".to_string()));
+    }
+    Ok(())
+}
+
+/// Reject building an in-memory, transformed response from a file too large for it.
+fn ensure_inline_size(state: &AppState, file: &File) -> Result<(), AppError> {
+    if file.file_size as u64 > state.config.max_inline_response_bytes {
+        return Err(AppError::PayloadTooLarge(format!(
+            "File is {} bytes, over the {} byte limit for this response; fetch it unmodified from GET /files/{}/download instead",
+            file.file_size, state.config.max_inline_response_bytes, file.id
+        )));
+    }
+    Ok(())
+}
+
+/// Parse an optional IANA timezone name from a `?tz=` query parameter.
+fn parse_tz(tz: Option<&str>) -> Result<Option<Tz>, AppError> {
+    tz.map(|name| {
+        name.parse::<Tz>()
+            .map_err(|_| AppError::BadRequest(format!("Unknown timezone '{}'", name)))
+    })
+    .transpose()
+}
+
+/// Parse a `?fields=` list, dropping (or with `strict`, rejecting) unknown names.
+fn parse_fields(fields: Option<&str>, strict: bool) -> Result<Option<Vec<&str>>, AppError> {
+    let Some(fields) = fields.filter(|f| !f.trim().is_empty()) else {
+        return Ok(None);
+    };
+
+    let mut selected = Vec::new();
+    for field in fields.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+        match FILE_RESPONSE_FIELDS.iter().find(|known| **known == field) {
+            Some(known) => selected.push(*known),
+            None if strict => {
+                return Err(AppError::BadRequest(format!(
+                    "Unknown field '{}'; allowed fields: {}",
+                    field,
+                    FILE_RESPONSE_FIELDS.join(", ")
+                )));
+            }
+            None => {}
+        }
+    }
+
+    Ok(Some(selected))
+}
+
+/// Serialize a file response keeping only the given keys.
+fn project_fields(file: &FileResponse, fields: &[&str]) -> Result<serde_json::Value, serde_json::Error> {
+    let mut value = serde_json::to_value(file)?;
+    if let serde_json::Value::Object(map) = &mut value {
+        map.retain(|key, _| fields.contains(&key.as_str()));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_fields_returns_none_without_a_selection() {
+        assert_eq!(parse_fields(None, true).unwrap(), None);
+        assert_eq!(parse_fields(Some(" "), true).unwrap(), None);
+    }
+
+    #[test]
+    fn parse_fields_keeps_known_fields_in_order() {
+        let fields = parse_fields(Some("size, id,,original_filename"), true).unwrap();
+        assert_eq!(fields, Some(vec!["size", "id", "original_filename"]));
+    }
+
+    #[test]
+    fn parse_fields_drops_or_rejects_unknown_fields() {
+        assert_eq!(parse_fields(Some("id,file_path"), false).unwrap(), Some(vec!["id"]));
+        assert!(matches!(parse_fields(Some("id,file_path"), true), Err(AppError::BadRequest(_))));
+    }
+
+    fn file_with_checksum(checksum: Option<&str>) -> File {
+        serde_json::from_value(serde_json::json!({
+            "id": Uuid::nil(),
+            "filename": "notes.txt",
+            "original_filename": "notes.txt",
+            "file_path": "uploads/files/notes.txt",
+            "file_size": 12,
+            "mime_type": "text/plain",
+            "storage_type": "local",
+            "flagged": false,
+            "gzip_precompressed": true,
+            "checksum": checksum,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn download_etag_distinguishes_each_representation() {
+        let file = file_with_checksum(Some("abc123"));
+        assert_eq!(download_etag(&file, false, None).as_deref(), Some("\"abc123\""));
+        assert_eq!(download_etag(&file, true, None).as_deref(), Some("\"abc123-gzip\""));
+        assert_eq!(
+            download_etag(&file, false, Some(encoding_rs::WINDOWS_1252)).as_deref(),
+            Some("\"abc123-windows-1252\"")
+        );
+    }
+
+    #[test]
+    fn download_etag_needs_a_checksum() {
+        assert_eq!(download_etag(&file_with_checksum(None), false, None), None);
+    }
+
+    #[test]
+    fn metadata_etag_changes_with_the_metadata() {
+        let file = file_with_checksum(Some("abc123"));
+        let etag = metadata_etag(&file);
+        assert!(etag.starts_with('"') && etag.ends_with('"'), "{}", etag);
+        assert_eq!(etag, metadata_etag(&file_with_checksum(Some("abc123"))));
+
+        let mut updated = file_with_checksum(Some("abc123"));
+        updated.updated_at = Some(chrono::Utc::now());
+        assert_ne!(metadata_etag(&updated), etag);
+        assert_ne!(metadata_etag(&file_with_checksum(Some("def456"))), etag);
+    }
+
+    #[test]
+    fn etag_matches_uses_weak_comparison() {
+        assert!(etag_matches("\"abc\"", "\"abc\""));
+        assert!(etag_matches("W/\"abc\"", "\"abc\""));
+        assert!(etag_matches("\"abc\"", "W/\"abc\""));
+        assert!(!etag_matches("\"abd\"", "\"abc\""));
+        assert!(!etag_matches("abc", "\"abc\""));
+    }
+
+    #[test]
+    fn etag_matches_accepts_wildcards_and_lists() {
+        assert!(etag_matches("*", "\"abc\""));
+        assert!(etag_matches("\"x\", W/\"abc\" ,\"y\"", "\"abc\""));
+        assert!(!etag_matches("\"x\", \"y\"", "\"abc\""));
+        assert!(!etag_matches("", "\"abc\""));
+    }
+
+    #[test]
+    fn public_paths_must_stay_under_the_prefix() {
+        assert!(is_plain_relative_path("logo.png"));
+        assert!(is_plain_relative_path("css/site.v2.css"));
+        assert!(is_plain_relative_path("..hidden/file"));
+
+        assert!(!is_plain_relative_path(""));
+        assert!(!is_plain_relative_path("/etc/passwd"));
+        assert!(!is_plain_relative_path("../files/secret.pdf"));
+        assert!(!is_plain_relative_path("css/../../files/secret.pdf"));
+        assert!(!is_plain_relative_path("css/./site.css"));
+        assert!(!is_plain_relative_path("css//site.css"));
+        assert!(!is_plain_relative_path("..\\files\\secret.pdf"));
+    }
+
+    #[tokio::test]
+    async fn expired_files_are_gone_before_cleanup() {
+        let state = AppState::for_tests(&[("DEFAULT_RETENTION_DAYS", "30")]).await;
+        let now = chrono::Utc::now();
+        let mut file = file_with_checksum(None);
+        file.uploaded_at = Some(now - chrono::Duration::days(1));
+        assert!(ensure_retained(&state, &file).is_ok());
+
+        file.expires_at = Some(now - chrono::Duration::seconds(1));
+        let err = ensure_retained(&state, &file).unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::GONE);
+
+        file.expires_at = None;
+        file.uploaded_at = Some(now - chrono::Duration::days(31));
+        let err = ensure_retained(&state, &file).unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::GONE);
+    }
+
+    #[tokio::test]
+    async fn manifest_entry_carries_verification_data() {
+        let state = AppState::for_tests(&[]).await;
+        let mut file = file_with_checksum(Some("abc123"));
+        file.md5 = Some("0cc175b9c0f1b6a831c399e269772661".to_string());
+
+        let entry = manifest_entry(&state, file, chrono::Utc::now()).await.unwrap();
+        assert_eq!(entry.manifest.checksum.as_deref(), Some("abc123"));
+        assert_eq!(entry.manifest.md5.as_deref(), Some("0cc175b9c0f1b6a831c399e269772661"));
+        assert_eq!(entry.manifest.etag.as_deref(), Some("\"abc123\""));
+        // In-memory storage can't presign, so the manifest points at the download route
+        assert_eq!(entry.manifest.download_url, format!("/files/{}/download", Uuid::nil()));
+        assert!(!entry.presigned);
+        assert_eq!(entry.expires_at, None);
+    }
+
+    #[tokio::test]
+    async fn storage_objects_include_cached_conversions() {
+        let state = AppState::for_tests(&[]).await;
+        let mut file = file_with_checksum(None);
+        file.file_path = "memory://files/notes.txt".to_string();
+        file.storage_type = "memory".to_string();
+        let conversion = format!("conversions/{}.webp", file.id);
+        state.storage.upload(&conversion, Bytes::from_static(b"webp")).await.unwrap();
+        state.storage.upload("conversions/other.webp", Bytes::from_static(b"webp")).await.unwrap();
+
+        let objects = storage_objects(&state, &file).await;
+        assert_eq!(objects, vec!["files/notes.txt".to_string(), "files/notes.txt.gz".to_string(), conversion]);
+
+        // A real delete removes exactly what the dry run lists
+        for object in &objects {
+            state.storage.upload(object, Bytes::from_static(b"x")).await.unwrap();
         }
-    }).collect();
+        delete_stored_objects(&state, &file).await.unwrap();
+        for object in &objects {
+            assert!(!state.storage.exists(object).await.unwrap());
+        }
+        assert!(state.storage.exists("conversions/other.webp").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn repeat_downloads_are_served_from_the_cache() {
+        let state = AppState::for_tests(&[]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DownloadCache::new(dir.path().to_str().unwrap(), 1024, None).await.unwrap();
+        state.storage.upload("files/a.bin", Bytes::from_static(b"hello")).await.unwrap();
+
+        let first = read_through_cache(&state, Some(&cache), "files/a.bin").await.unwrap();
+        // Gone from storage, so only the cache can answer the second download
+        state.storage.delete("files/a.bin").await.unwrap();
+        let second = read_through_cache(&state, Some(&cache), "files/a.bin").await.unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(cache.stats().hits, 1);
+        assert!(read_through_cache(&state, None, "files/a.bin").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn expired_cache_entries_are_refetched() {
+        let state = AppState::for_tests(&[]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DownloadCache::new(dir.path().to_str().unwrap(), 1024, Some(Duration::from_millis(50)))
+            .await
+            .unwrap();
+        state.storage.upload("files/a.bin", Bytes::from_static(b"old")).await.unwrap();
+        read_through_cache(&state, Some(&cache), "files/a.bin").await.unwrap();
+
+        state.storage.upload("files/a.bin", Bytes::from_static(b"new")).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        let content = read_through_cache(&state, Some(&cache), "files/a.bin").await.unwrap();
+        assert_eq!(content, Bytes::from_static(b"new"));
+        assert_eq!(cache.stats().hits, 0);
+    }
+
+    #[test]
+    fn check_disk_space_refuses_uploads_that_would_fill_the_disk() {
+        assert!(check_disk_space(Some(1_000), 400, 600).is_ok());
+
+        let err = check_disk_space(Some(999), 400, 600).unwrap_err();
+        assert!(matches!(err, AppError::InsufficientStorage(_)));
+        assert_eq!(err.into_response().status(), StatusCode::INSUFFICIENT_STORAGE);
+
+        assert!(check_disk_space(Some(u64::MAX - 1), u64::MAX, 1).is_err());
+    }
+
+    #[test]
+    fn check_disk_space_allows_backends_without_a_limit() {
+        assert!(check_disk_space(None, u64::MAX, u64::MAX).is_ok());
+    }
+
+    #[tokio::test]
+    async fn generated_urls_include_the_public_base_url() {
+        let state = AppState::for_tests(&[("PUBLIC_BASE_URL", "https://example.com/api/")]).await;
+        let mut file = file_with_checksum(None);
+        file.thumbnail_path = Some("uploads/thumbnails/notes.png".to_string());
 
-    // Return the list as a JSON array
-    Ok(Json(response))
-}
\ No newline at end of file
+        let response = file_response(&state, file, None);
+        assert_eq!(
+            response.download_url,
+            format!("https://example.com/api/files/{}/download", Uuid::nil())
+        );
+        assert_eq!(
+            response.thumbnail_url,
+            Some(format!("https://example.com/api/files/{}/thumbnail", Uuid::nil()))
+        );
+    }
+
+    #[tokio::test]
+    async fn generated_urls_stay_relative_without_a_base_url() {
+        let state = AppState::for_tests(&[]).await;
+        let response = file_response(&state, file_with_checksum(None), None);
+        assert_eq!(response.download_url, format!("/files/{}/download", Uuid::nil()));
+        assert_eq!(response.thumbnail_url, None);
+    }
+
+    async fn state_with_pool(pool: sqlx::PgPool, vars: &[(&str, &str)]) -> AppState {
+        let mut state = AppState::for_tests(vars).await;
+        state.pool = pool;
+        state
+    }
+
+    /// Store `data` as `name` through the buffered upload path.
+    async fn store_bytes(state: &AppState, name: &str, data: &[u8]) -> UploadResponse {
+        let response = store_file(state, NewUpload {
+            content: UploadContent::Buffered(Bytes::copy_from_slice(data)),
+            digests: ContentDigests::of(data, state.config.checksum_algorithm),
+            original_filename: name.to_string(),
+            mime_type: "text/plain".to_string(),
+            custom_filename: None,
+            ttl_seconds: None,
+        })
+        .await
+        .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[sqlx::test]
+    async fn dedup_strategy_decides_which_uploads_share_a_file(pool: sqlx::PgPool) {
+        for (strategy, same_name_shared, renamed_shared) in [
+            ("checksum", true, true),
+            ("checksum_and_name", true, false),
+            ("none", false, false),
+        ] {
+            let state = state_with_pool(pool.clone(), &[("DEDUP_STRATEGY", strategy)]).await;
+            let data = format!("quarterly report, deduplicated by {}", strategy);
+
+            let first = store_bytes(&state, "report.txt", data.as_bytes()).await;
+            let again = store_bytes(&state, "report.txt", data.as_bytes()).await;
+            let renamed = store_bytes(&state, "report-copy.txt", data.as_bytes()).await;
+
+            assert_eq!(first.id == again.id, same_name_shared, "{}", strategy);
+            assert_eq!(first.id == renamed.id, renamed_shared, "{}", strategy);
+        }
+    }
+}
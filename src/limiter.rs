@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use uuid::Uuid;
+
+/// Caps the number of concurrent downloads of any single file.
+#[derive(Clone)]
+pub struct DownloadLimiter {
+    per_file: usize,
+    semaphores: Arc<DashMap<Uuid, Arc<Semaphore>>>,
+}
+
+impl DownloadLimiter {
+    pub fn new(per_file: usize) -> Self {
+        Self {
+            per_file,
+            semaphores: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// Try to start a download of `id`, returning `None` when the file
+    /// already has the maximum number of downloads in flight.
+    pub fn try_acquire(&self, id: Uuid) -> Option<DownloadPermit> {
+        let semaphore = self
+            .semaphores
+            .entry(id)
+            .or_insert_with(|| Arc::new(Semaphore::new(self.per_file)))
+            .clone();
+
+        let permit = semaphore.try_acquire_owned().ok()?;
+
+        Some(DownloadPermit {
+            id,
+            permit: Some(permit),
+            semaphores: self.semaphores.clone(),
+        })
+    }
+}
+
+/// Held for the duration of a download; releases the slot on drop.
+pub struct DownloadPermit {
+    id: Uuid,
+    permit: Option<OwnedSemaphorePermit>,
+    semaphores: Arc<DashMap<Uuid, Arc<Semaphore>>>,
+}
+
+impl Drop for DownloadPermit {
+    fn drop(&mut self) {
+        self.permit.take();
+        // Forget the semaphore once nobody else holds it so the map doesn't grow forever
+        self.semaphores
+            .remove_if(&self.id, |_, semaphore| Arc::strong_count(semaphore) == 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_downloads_over_the_per_file_limit() {
+        let limiter = DownloadLimiter::new(2);
+        let id = Uuid::new_v4();
+
+        let first = limiter.try_acquire(id);
+        let second = limiter.try_acquire(id);
+        assert!(first.is_some() && second.is_some());
+        assert!(limiter.try_acquire(id).is_none());
+
+        // Other files have their own slots
+        assert!(limiter.try_acquire(Uuid::new_v4()).is_some());
+    }
+
+    #[test]
+    fn dropping_a_permit_frees_its_slot() {
+        let limiter = DownloadLimiter::new(1);
+        let id = Uuid::new_v4();
+
+        let permit = limiter.try_acquire(id).unwrap();
+        assert!(limiter.try_acquire(id).is_none());
+        drop(permit);
+
+        let permit = limiter.try_acquire(id);
+        assert!(permit.is_some());
+        drop(permit);
+        assert!(limiter.semaphores.is_empty());
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    collections::VecDeque,
+    fmt::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use chrono::Utc;
+use tracing::{
+    Event, Subscriber,
+    field::{Field, Visit},
+};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// Bounded in-memory buffer of the most recent log lines.
+#[derive(Clone)]
+pub struct LogBuffer {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// Append a line, evicting the oldest once the buffer is full.
+    fn push(&self, line: String) {
+        let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    /// Return up to `limit` of the most recent lines, oldest first.
+    pub fn recent(&self, limit: usize) -> Vec<String> {
+        let lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        let skip = lines.len().saturating_sub(limit);
+        lines.iter().skip(skip).cloned().collect()
+    }
+
+    /// Tracing layer that records every event into this buffer.
+    pub fn layer(&self) -> LogBufferLayer {
+        LogBufferLayer { buffer: self.clone() }
+    }
+}
+
+/// `tracing` layer feeding formatted events into a [`LogBuffer`].
+pub struct LogBufferLayer {
+    buffer: LogBuffer,
+}
+
+impl<S: Subscriber> Layer<S> for LogBufferLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut visitor = LineVisitor::default();
+        event.record(&mut visitor);
+
+        self.buffer.push(format!(
+            "{} {} {}: {}",
+            Utc::now().to_rfc3339(),
+            metadata.level(),
+            metadata.target(),
+            visitor.line
+        ));
+    }
+}
+
+/// Collects the event message followed by any `key=value` fields.
+#[derive(Default)]
+struct LineVisitor {
+    line: String,
+}
+
+impl Visit for LineVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.line, "{:?}", value);
+        } else {
+            let _ = write!(self.line, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn keeps_only_the_newest_lines() {
+        let buffer = LogBuffer::new(3);
+        for i in 0..5 {
+            buffer.push(format!("line {}", i));
+        }
+
+        assert_eq!(buffer.recent(10), vec!["line 2", "line 3", "line 4"]);
+        assert_eq!(buffer.recent(2), vec!["line 3", "line 4"]);
+        assert!(buffer.recent(0).is_empty());
+    }
+
+    #[test]
+    fn layer_records_level_target_and_fields() {
+        let buffer = LogBuffer::new(10);
+        let subscriber = tracing_subscriber::registry().with(buffer.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(target: "uploads", file_id = 7, "stored file");
+            tracing::error!(target: "storage", "bucket unreachable");
+        });
+
+        let lines = buffer.recent(10);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(" INFO uploads: stored file file_id=7"), "{}", lines[0]);
+        assert!(lines[1].ends_with(" ERROR storage: bucket unreachable"), "{}", lines[1]);
+    }
+}
@@ -6,69 +6,535 @@ mod state;
 mod storage;
 mod handlers;
 mod error;
+mod middleware;
+mod logs;
+mod query;
+mod limiter;
+mod cache;
+mod breaker;
+mod uploads;
 
-use axum::{routing::{post, get, delete}, Router};
-use std::net::SocketAddr;
-use tracing_subscriber;
-use tracing::info;
+use axum::{
+    Json, Router,
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, HeaderName, StatusCode, header},
+    middleware::{from_fn, from_fn_with_state},
+    response::{IntoResponse, Response},
+    routing::{post, get, delete},
+};
+use serde_json::json;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto::Builder,
+    service::TowerToHyperService,
+};
+use std::{net::SocketAddr, sync::{Arc, atomic::{AtomicBool, Ordering}}, time::Duration};
+use tokio::net::TcpListener;
+use tracing_subscriber::{filter::LevelFilter, layer::SubscriberExt, util::SubscriberInitExt};
+use tracing::{debug, error, info};
 use tower_http::{
     cors::{Any, CorsLayer},
+    add_extension::AddExtension,
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
     trace::TraceLayer,
 };
 
 use crate::{
-    handlers::{upload_file, download_file, delete_file, get_thummbnail, get_file, list_files},
+    handlers::{upload_file, upload_raw, download_file, head_download, verify_file, convert_file, file_qr_code, preview_file, presigned_url, delete_file, get_thummbnail, get_file, get_manifest, download_manifest, purge_expired_files, serve_public, list_files, stream_files, set_maintenance, backfill_checksums, recent_logs, storage_stats, public_storage_stats, broken_files, file_access_log, file_storage_details, cache_stats, purge_cache},
+    middleware::{maintenance_guard, require_admin, require_multipart},
+    uploads::{create_upload_session, get_upload_session, append_upload_chunk, complete_upload, purge_stale_upload_sessions},
+    logs::LogBuffer,
+    limiter::DownloadLimiter,
+    cache::DownloadCache,
+    breaker::CircuitBreaker,
     state::AppState,
-    config::Config,
+    config::{Config, HealthFormat, StorageKind},
     database::init_db,
-    storage::init_storage,
+    storage::{clean_staging, init_storage, wait_until_ready},
 };
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    tracing_subscriber::fmt::init();
-
     let config = Config::from_env()
         .expect("Failed to load configuration");
 
+    // Log to stdout and keep the most recent lines in memory for the admin API
+    let logs = LogBuffer::new(config.log_buffer_lines);
+    tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(tracing_subscriber::fmt::layer())
+        .with(logs.layer())
+        .init();
+
     let pool = init_db(&config.database_url)
         .await
         .expect("Failed to connect to db");
 
     let storage = init_storage(&config).await;
 
+    if config.allowed_extensions.is_empty() {
+        info!("ALLOWED_EXTENSIONS is empty; uploads of any file extension are accepted");
+    } else {
+        info!("Allowed file extensions: {}", config.allowed_extensions.join(", "));
+    }
+
+    let header_read_timeout = Duration::from_secs(config.header_read_timeout_secs);
+
+    let download_limiter = (config.max_concurrent_downloads_per_file > 0)
+        .then(|| DownloadLimiter::new(config.max_concurrent_downloads_per_file));
+
+    // Only remote objects are worth caching on local disk
+    let download_cache = match &config.download_cache_dir {
+        Some(dir) if matches!(config.storage_backend, StorageKind::S3 | StorageKind::Azure) => Some(
+            DownloadCache::new(
+                dir,
+                config.download_cache_max_bytes,
+                (config.download_cache_ttl_seconds > 0)
+                    .then(|| Duration::from_secs(config.download_cache_ttl_seconds)),
+            )
+                .await
+                .expect("Failed to create download cache directory"),
+        ),
+        _ => None,
+    };
+
+    let upload_breaker = (config.storage_breaker_failure_threshold > 0).then(|| {
+        CircuitBreaker::new(
+            config.storage_breaker_failure_threshold,
+            Duration::from_millis(config.storage_breaker_latency_ms),
+            Duration::from_secs(config.storage_breaker_cooldown_secs),
+        )
+    });
+
+    let watermark = config.watermark_image_path.as_ref().map(|path| {
+        let image = image::open(path).expect("Failed to load watermark image");
+        Arc::new(image.to_rgba8())
+    });
+
     let app_state = AppState {
         pool,
         storage,
-        config
+        config,
+        maintenance: Arc::new(AtomicBool::new(false)),
+        ready: Arc::new(AtomicBool::new(false)),
+        logs,
+        watermark,
+        download_limiter,
+        download_cache,
+        upload_breaker,
     };
 
+    // Report ready only after storage has accepted a test write (e.g. the S3
+    // bucket may still be being created or be eventually consistent)
+    {
+        let storage = app_state.storage.clone();
+        let ready = app_state.ready.clone();
+        tokio::spawn(async move { wait_until_ready(&storage, &ready, Duration::from_secs(2)).await });
+    }
+
+    // Periodically remove staged and streamed uploads abandoned mid-upload
+    {
+        let storage = app_state.storage.clone();
+        let pool = app_state.pool.clone();
+        let max_age = Duration::from_secs(app_state.config.staging_max_age_minutes * 60);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                match clean_staging(&storage, &pool, max_age).await {
+                    Ok(0) => {}
+                    Ok(removed) => info!("Removed {} stale staged uploads", removed),
+                    Err(e) => error!("Failed to clean staged uploads: {}", e),
+                }
+            }
+        });
+    }
+
+    // Periodically delete files past their TTL or retention period, and
+    // resumable uploads abandoned by their clients
+    {
+        let state = app_state.clone();
+        let period = Duration::from_secs(app_state.config.cleanup_interval_secs);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                match purge_expired_files(&state).await {
+                    Ok(0) => {}
+                    Ok(removed) => info!("Removed {} expired files", removed),
+                    Err(e) => error!("Failed to remove expired files: {}", e),
+                }
+                match purge_stale_upload_sessions(&state).await {
+                    Ok(0) => {}
+                    Ok(removed) => info!("Removed {} stale upload sessions", removed),
+                    Err(e) => error!("Failed to remove stale upload sessions: {}", e),
+                }
+            }
+        });
+    }
+
+    let app = build_router(app_state);
+
+    let addr = SocketAddr::from(([0,0,0,0], 3000));
+    info!("Server listening on {}", addr);
+
+    let listener = TcpListener::bind(addr).await.unwrap();
+    serve(listener, app, header_read_timeout).await;
+
+    Ok(())
+}
+
+/// All routes with their middleware, admin routes nested under `/admin`.
+fn build_router(state: AppState) -> Router {
+    let request_id_header = HeaderName::from_bytes(state.config.request_id_header.as_bytes())
+        .expect("Invalid REQUEST_ID_HEADER");
+
+    // Let browser clients read the headers this service sets, including the request ID
+    let expose_headers: Vec<HeaderName> = state
+        .config
+        .cors_expose_headers
+        .iter()
+        .map(|name| HeaderName::from_bytes(name.as_bytes()).expect("Invalid CORS_EXPOSE_HEADERS"))
+        .chain(std::iter::once(request_id_header.clone()))
+        .collect();
+
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
-        .allow_headers(Any);
+        .allow_headers(Any)
+        .expose_headers(expose_headers);
+
+    // Admin routes require the bearer token configured via ADMIN_TOKEN
+    let admin = Router::new()
+        .route("/maintenance", post(set_maintenance))
+        .route("/backfill-checksums", post(backfill_checksums))
+        .route("/logs/recent", get(recent_logs))
+        .route("/files/broken", get(broken_files))
+        .route("/files/{id}/access-log", get(file_access_log))
+        .route("/files/{id}/storage", get(file_storage_details))
+        .route("/cache/stats", get(cache_stats))
+        .route("/cache/purge", post(purge_cache))
+        .route("/stats/storage", get(storage_stats))
+        .route_layer(from_fn_with_state(state.clone(), require_admin));
 
-    let app = Router::new()
+    Router::new()
         .route("/health", get(health_check))
-        .route("/upload", post(upload_file))
-        .route("/files/{id}/download", get(download_file))
+        .route("/health/ready", get(readiness_check))
+        .route("/upload", post(upload_file).route_layer(from_fn(require_multipart)))
+        .route("/upload/raw", post(upload_raw))
+        .route("/uploads", post(create_upload_session))
+        .route("/uploads/{id}", get(get_upload_session).patch(append_upload_chunk))
+        .route("/uploads/{id}/complete", post(complete_upload))
+        .route("/files/{id}/download", get(download_file).head(head_download))
         .route("/files/{id}/thumbnail", get(get_thummbnail))
+        .route("/files/{id}/verify", get(verify_file))
+        .route("/files/{id}/convert", get(convert_file))
+        .route("/files/{id}/manifest", get(get_manifest))
+        .route("/files/download-manifest", post(download_manifest))
+        .route("/files/{id}/qr", get(file_qr_code))
+        .route("/files/{id}/preview.html", get(preview_file))
+        .route("/files/{id}/presigned", get(presigned_url))
         .route("/files/{id}", get(get_file))
         .route("/files", get(list_files))
+        .route("/files/search/stream", get(stream_files))
+        .route("/stats/storage", get(public_storage_stats))
+        .route("/public/{*path}", get(serve_public))
         .route("/files/{id}", delete(delete_file))
+        .nest("/admin", admin)
+        .layer(from_fn_with_state(state.clone(), maintenance_guard))
         .layer(cors)
         .layer(TraceLayer::new_for_http())
-        .with_state(app_state);
-    
-    let addr = SocketAddr::from(([0,0,0,0], 3000));
-    info!("Server listening on {}", addr);
+        // Reuse the caller's request ID (or generate one) and echo it on the response
+        .layer(PropagateRequestIdLayer::new(request_id_header.clone()))
+        .layer(SetRequestIdLayer::new(request_id_header, MakeRequestUuid))
+        .with_state(state)
+}
 
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+/// Accept connections and serve them with hyper directly so a header read timeout
+/// can be applied: connections that never send (or stall while sending) request
+/// headers, including idle keep-alive connections, are closed once it elapses.
+async fn serve(listener: TcpListener, app: Router, header_read_timeout: Duration) {
+    loop {
+        let (stream, remote_addr) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
 
-    Ok(())
+        // Expose the peer address to handlers through the `ConnectInfo` extractor
+        let service = TowerToHyperService::new(AddExtension::new(app.clone(), ConnectInfo(remote_addr)));
+
+        tokio::spawn(async move {
+            let mut builder = Builder::new(TokioExecutor::new());
+            builder
+                .http1()
+                .timer(TokioTimer::new())
+                .header_read_timeout(header_read_timeout);
+
+            if let Err(e) = builder
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("Connection from {} closed: {}", remote_addr, e);
+            }
+        });
+    }
+}
+
+/// Health check returning `{"status":"ok"}` or a plain `OK`.
+/// An explicit `Accept` header wins over the configured default format.
+async fn health_check(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+
+    let format = if accept.contains("application/json") {
+        HealthFormat::Json
+    } else if accept.contains("text/plain") {
+        HealthFormat::Plain
+    } else {
+        state.config.health_format
+    };
+
+    match format {
+        HealthFormat::Json => Json(json!({"status": "ok"})).into_response(),
+        HealthFormat::Plain => "OK".into_response(),
+    }
+}
+
+/// Readiness check: `503` until the storage backend has passed its probe.
+async fn readiness_check(State(state): State<AppState>) -> Response {
+    if state.ready.load(Ordering::Relaxed) {
+        Json(json!({"status": "ready"})).into_response()
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({"status": "starting"})),
+        )
+            .into_response()
+    }
 }
 
-async fn health_check() -> &'static str {
-    "OK"
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request};
+    use bytes::Bytes;
+    use tower::ServiceExt;
+
+    use crate::storage::Storage;
+
+    #[tokio::test]
+    async fn maintenance_rejects_writes_but_keeps_serving_reads() {
+        let state = AppState::for_tests(&[("PUBLIC_PREFIX", "public")]).await;
+        state.storage.upload("public/logo.txt", Bytes::from_static(b"logo")).await.unwrap();
+        state.maintenance.store(true, Ordering::Relaxed);
+        let app = build_router(state);
+
+        let upload = Request::post("/upload/raw")
+            .header("x-filename", "notes.txt")
+            .body(Body::from("hello"))
+            .unwrap();
+        let response = app.clone().oneshot(upload).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let delete = Request::delete(format!("/files/{}", uuid::Uuid::nil())).body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(delete).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let download = Request::get("/public/logo.txt").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(download).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"logo");
+
+        // Read-only POSTs reach their handler, which rejects this empty request itself
+        let manifest = Request::post("/files/download-manifest")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"ids": []}"#))
+            .unwrap();
+        let response = app.oneshot(manifest).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn recent_logs_returns_captured_events() {
+        let state = AppState::for_tests(&[("ADMIN_TOKEN", "secret"), ("LOG_BUFFER_LINES", "2")]).await;
+        let subscriber = tracing_subscriber::registry().with(state.logs.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            info!("first");
+            info!("second");
+            error!("third");
+        });
+        let app = build_router(state);
+
+        let unauthorized = Request::get("/admin/logs/recent").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(unauthorized).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let request = Request::get("/admin/logs/recent")
+            .header(header::AUTHORIZATION, "Bearer secret")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let logs: models::RecentLogsResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(logs.lines.len(), 2);
+        assert!(logs.lines[0].contains("INFO") && logs.lines[0].ends_with("second"), "{}", logs.lines[0]);
+        assert!(logs.lines[1].contains("ERROR") && logs.lines[1].ends_with("third"), "{}", logs.lines[1]);
+    }
+
+    async fn get_health(app: &Router, accept: Option<&str>) -> (Option<String>, Bytes) {
+        let mut request = Request::get("/health");
+        if let Some(accept) = accept {
+            request = request.header(header::ACCEPT, accept);
+        }
+        let response = app.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .map(|v| v.to_str().unwrap().to_string());
+        (content_type, axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn health_defaults_to_json_unless_configured_plain() {
+        let app = build_router(AppState::for_tests(&[]).await);
+        let (content_type, body) = get_health(&app, None).await;
+        assert_eq!(content_type.as_deref(), Some("application/json"));
+        assert_eq!(&body[..], br#"{"status":"ok"}"#);
+
+        let app = build_router(AppState::for_tests(&[("HEALTH_FORMAT", "plain")]).await);
+        let (content_type, body) = get_health(&app, None).await;
+        assert_eq!(content_type.as_deref(), Some("text/plain; charset=utf-8"));
+        assert_eq!(&body[..], b"OK");
+    }
+
+    #[tokio::test]
+    async fn health_accept_header_overrides_the_configured_format() {
+        let app = build_router(AppState::for_tests(&[("HEALTH_FORMAT", "plain")]).await);
+        let (_, body) = get_health(&app, Some("application/json")).await;
+        assert_eq!(&body[..], br#"{"status":"ok"}"#);
+
+        let app = build_router(AppState::for_tests(&[]).await);
+        let (_, body) = get_health(&app, Some("text/plain")).await;
+        assert_eq!(&body[..], b"OK");
+    }
+
+    const BOUNDARY: &str = "test-boundary";
+
+    /// `POST /upload` with a `file` part named `filename` followed by the given text fields.
+    fn upload_request(filename: &str, data: &str, fields: &[(&str, &str)]) -> Request<Body> {
+        let mut body = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: text/plain\r\n\r\n{}\r\n",
+            BOUNDARY, filename, data
+        );
+        for (name, value) in fields {
+            body.push_str(&format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                BOUNDARY, name, value
+            ));
+        }
+        body.push_str(&format!("--{}--\r\n", BOUNDARY));
+
+        Request::post("/upload")
+            .header(header::CONTENT_TYPE, format!("multipart/form-data; boundary={}", BOUNDARY))
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    async fn json_body(response: Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[sqlx::test]
+    async fn content_type_field_overrides_the_part_type(pool: sqlx::PgPool) {
+        let mut state = AppState::for_tests(&[]).await;
+        state.pool = pool;
+        let request = upload_request("notes.txt", "# Notes", &[("content_type", "text/markdown; charset=utf-8")]);
+
+        let response = build_router(state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(json_body(response).await["mime_type"], "text/markdown; charset=utf-8");
+    }
+
+    #[tokio::test]
+    async fn malformed_content_type_field_is_rejected() {
+        let app = build_router(AppState::for_tests(&[]).await);
+        let request = upload_request("notes.txt", "# Notes", &[("content_type", "not a mime type")]);
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(response).await["error"], "Invalid content_type: not a mime type");
+    }
+
+    #[tokio::test]
+    async fn uploads_without_multipart_content_type_are_unsupported() {
+        let app = build_router(AppState::for_tests(&[]).await);
+
+        for content_type in [Some("application/json"), Some("multipart/mixed; boundary=x"), None] {
+            let mut request = Request::post("/upload");
+            if let Some(content_type) = content_type {
+                request = request.header(header::CONTENT_TYPE, content_type);
+            }
+            let response = app.clone().oneshot(request.body(Body::from("{}")).unwrap()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            assert_eq!(
+                json_body(response).await["error"],
+                format!("Expected Content-Type multipart/form-data, got '{}'", content_type.unwrap_or(""))
+            );
+        }
+    }
+
+    #[sqlx::test]
+    async fn uploads_between_the_soft_and_hard_limits_are_flagged(pool: sqlx::PgPool) {
+        let mut state = AppState::for_tests(&[("SOFT_MAX_FILE_SIZE", "10"), ("MAX_FILE_SIZE", "20")]).await;
+        state.pool = pool.clone();
+        let app = build_router(state);
+
+        let mut flags = Vec::new();
+        for data in ["small", "fifteen bytes.."] {
+            let response = app.clone().oneshot(upload_request("notes.txt", data, &[])).await.unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+            let id: uuid::Uuid = json_body(response).await["id"].as_str().unwrap().parse().unwrap();
+            let flagged = sqlx::query_scalar!("SELECT flagged FROM files WHERE id = $1", id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            flags.push(flagged);
+        }
+        assert_eq!(flags, vec![false, true]);
+    }
+
+    #[tokio::test]
+    async fn uploads_above_the_hard_limit_are_rejected() {
+        let app = build_router(AppState::for_tests(&[("SOFT_MAX_FILE_SIZE", "10"), ("MAX_FILE_SIZE", "20")]).await);
+
+        let response = app.oneshot(upload_request("notes.txt", "twenty-one bytes long", &[])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn uploads_shorter_than_their_declared_size_are_rejected() {
+        let app = build_router(AppState::for_tests(&[]).await);
+
+        let response = app.oneshot(upload_request("notes.txt", "truncated", &[("size", "1024")])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(response).await["error"], "Declared size 1024 does not match 9 bytes received");
+    }
+
+    #[tokio::test]
+    async fn unparseable_declared_sizes_are_rejected() {
+        let app = build_router(AppState::for_tests(&[]).await);
+
+        let response = app.oneshot(upload_request("notes.txt", "truncated", &[("size", "lots")])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(response).await["error"], "Invalid size: lots");
+    }
 }
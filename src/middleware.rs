@@ -0,0 +1,85 @@
+use std::sync::atomic::Ordering;
+
+use axum::{
+    extract::{Request, State},
+    http::{Method, header},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{error::AppError, state::AppState};
+
+/// `POST` routes that only read, taking their input as a body because it
+/// doesn't fit in a query string.
+const READ_ONLY_POSTS: &[&str] = &["/files/download-manifest"];
+
+/// Reject mutating requests with `503` while maintenance mode is enabled.
+/// Read-only routes and the admin API keep working so the flag can be cleared.
+pub async fn maintenance_guard(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let path = request.uri().path();
+    let is_mutating = match *request.method() {
+        Method::POST => !READ_ONLY_POSTS.contains(&path),
+        Method::PUT | Method::PATCH | Method::DELETE => true,
+        _ => false,
+    };
+    let is_admin = path.starts_with("/admin");
+
+    if is_mutating && !is_admin && state.maintenance.load(Ordering::Relaxed) {
+        return Err(AppError::ServiceUnavailable(
+            "Service is in maintenance mode; write operations are temporarily disabled".into(),
+        ));
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Require `Authorization: Bearer <ADMIN_TOKEN>` on admin routes.
+/// When no admin token is configured the admin API is disabled entirely.
+pub async fn require_admin(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let expected = state.config.admin_token.as_deref().ok_or_else(|| {
+        AppError::Unauthorized("Admin API is disabled".into())
+    })?;
+
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    if provided != Some(expected) {
+        return Err(AppError::Unauthorized("Invalid or missing admin token".into()));
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Reject uploads whose `Content-Type` isn't `multipart/form-data` with a clear `415`
+/// before the multipart extractor gets a chance to fail with a less helpful error.
+pub async fn require_multipart(request: Request, next: Next) -> Result<Response, AppError> {
+    let content_type = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+
+    let is_multipart = content_type
+        .parse::<mime::Mime>()
+        .is_ok_and(|m| m.type_() == mime::MULTIPART && m.subtype() == mime::FORM_DATA);
+
+    if !is_multipart {
+        return Err(AppError::UnSupportedMediaType(format!(
+            "Expected Content-Type multipart/form-data, got '{}'",
+            content_type
+        )));
+    }
+
+    Ok(next.run(request).await)
+}
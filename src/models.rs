@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, FixedOffset, Utc};
 use uuid::Uuid;
 
 
@@ -17,6 +17,82 @@ pub struct File {
     pub thumbnail_path: Option<String>,
     pub uploaded_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
+    pub audio_duration_ms: Option<i64>,
+    pub audio_bitrate_kbps: Option<i32>,
+    pub audio_title: Option<String>,
+    pub audio_artist: Option<String>,
+    pub audio_album: Option<String>,
+    pub thumbnail_status: Option<String>,
+    pub flagged: bool,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub normalized_checksum: Option<String>,
+    pub thumbnails: Option<serde_json::Value>,
+    pub gzip_precompressed: bool,
+    pub md5: Option<String>,
+}
+
+/// State of a file's thumbnail. Stored in `files.thumbnail_status` as
+/// `pending`/`ok`/`failed`, or NULL for files that never get one (non-images),
+/// and reported to clients as `none`/`pending`/`ready`/`failed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThumbnailStatus {
+    None,
+    Pending,
+    Ready,
+    Failed,
+}
+
+/// Rendered thumbnail sizes, selected with `GET /files/{id}/thumbnail?size=`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThumbnailSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl ThumbnailSize {
+    /// Every size, smallest first.
+    pub const ALL: [ThumbnailSize; 3] = [ThumbnailSize::Small, ThumbnailSize::Medium, ThumbnailSize::Large];
+
+    /// Key of this size in `files.thumbnails`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ThumbnailSize::Small => "small",
+            ThumbnailSize::Medium => "medium",
+            ThumbnailSize::Large => "large",
+        }
+    }
+}
+
+/// Query parameters accepted by `GET /files/{id}/thumbnail`.
+#[derive(Debug, Deserialize)]
+pub struct ThumbnailQuery {
+    /// Defaults to the primary (medium) thumbnail.
+    pub size: Option<ThumbnailSize>,
+}
+
+impl ThumbnailStatus {
+    /// Value stored in `files.thumbnail_status`.
+    pub fn as_column(&self) -> Option<&'static str> {
+        match self {
+            ThumbnailStatus::None => None,
+            ThumbnailStatus::Pending => Some("pending"),
+            ThumbnailStatus::Ready => Some("ok"),
+            ThumbnailStatus::Failed => Some("failed"),
+        }
+    }
+
+    /// Parse a stored `files.thumbnail_status`; unknown values read as `None`.
+    pub fn from_column(value: Option<&str>) -> Self {
+        match value {
+            Some("pending") => ThumbnailStatus::Pending,
+            Some("ok") => ThumbnailStatus::Ready,
+            Some("failed") => ThumbnailStatus::Failed,
+            _ => ThumbnailStatus::None,
+        }
+    }
 }
 
 
@@ -29,6 +105,25 @@ pub struct UploadResponse {
     pub mime_type: String,
 }
 
+/// Keys of `FileResponse` that can be selected with `?fields=`.
+pub const FILE_RESPONSE_FIELDS: &[&str] = &[
+    "id",
+    "filename",
+    "original_filename",
+    "size",
+    "mime_type",
+    "uploaded_at",
+    "uploaded_at_epoch_ms",
+    "uploaded_at_local",
+    "updated_at",
+    "download_url",
+    "thumbnail_url",
+    "thumbnail_status",
+    "category",
+    "audio",
+    "flagged",
+];
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FileResponse {
     pub id: Uuid,
@@ -37,6 +132,484 @@ pub struct FileResponse {
     pub size: i64,
     pub mime_type: String,
     pub uploaded_at: Option<DateTime<Utc>>,
+    pub uploaded_at_epoch_ms: Option<i64>,
+    /// `uploaded_at` in the timezone requested with `?tz=`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uploaded_at_local: Option<DateTime<FixedOffset>>,
+    /// Last change to the file's row, maintained by the `update_files_updated_at` trigger.
+    pub updated_at: Option<DateTime<Utc>>,
     pub download_url: String,
+    /// Null until `thumbnail_status` is `ready`.
     pub thumbnail_url: Option<String>,
+    pub thumbnail_status: ThumbnailStatus,
+    pub category: Category,
+    pub audio: Option<AudioMetadata>,
+    /// Set when the upload exceeded the soft size limit.
+    pub flagged: bool,
+}
+
+/// Metadata extracted from audio files; tag fields are null when absent.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AudioMetadata {
+    pub duration_ms: Option<i64>,
+    pub bitrate_kbps: Option<i32>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+}
+
+impl File {
+    /// Audio metadata stored for this file, if it is an audio file.
+    pub fn audio_metadata(&self) -> Option<AudioMetadata> {
+        if !self.mime_type.starts_with("audio/") {
+            return None;
+        }
+
+        Some(AudioMetadata {
+            duration_ms: self.audio_duration_ms,
+            bitrate_kbps: self.audio_bitrate_kbps,
+            title: self.audio_title.clone(),
+            artist: self.audio_artist.clone(),
+            album: self.audio_album.clone(),
+        })
+    }
+
+    /// Whether this file is past its `expires_at` or older than `retention_days`
+    /// (0 disables age-based retention).
+    pub fn is_expired(&self, retention_days: u32, now: DateTime<Utc>) -> bool {
+        if self.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return true;
+        }
+
+        retention_days > 0
+            && self.uploaded_at.is_some_and(|uploaded_at| {
+                uploaded_at + chrono::Duration::days(i64::from(retention_days)) <= now
+            })
+    }
+
+    /// Stored path of the thumbnail closest to `size`, preferring the larger
+    /// neighbour on a tie. Files from before multiple sizes fall back to `thumbnail_path`.
+    pub fn thumbnail_for(&self, size: ThumbnailSize) -> Option<String> {
+        let requested = ThumbnailSize::ALL.iter().position(|s| *s == size).unwrap_or(1);
+        let mut candidates: Vec<(usize, ThumbnailSize)> = ThumbnailSize::ALL.into_iter().enumerate().collect();
+        candidates.sort_by_key(|(i, _)| (i.abs_diff(requested), std::cmp::Reverse(*i)));
+
+        candidates
+            .into_iter()
+            .find_map(|(_, size)| {
+                self.thumbnails
+                    .as_ref()?
+                    .get(size.as_str())?
+                    .as_str()
+                    .map(str::to_string)
+            })
+            .or_else(|| self.thumbnail_path.clone())
+    }
+
+    /// Stored paths of every thumbnail rendered for this file.
+    pub fn thumbnail_keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self
+            .thumbnails
+            .as_ref()
+            .and_then(|thumbnails| thumbnails.as_object())
+            .map(|sizes| sizes.values().filter_map(|key| key.as_str().map(str::to_string)).collect())
+            .unwrap_or_default();
+
+        if let Some(path) = &self.thumbnail_path {
+            if !keys.contains(path) {
+                keys.push(path.clone());
+            }
+        }
+        keys
+    }
+
+    /// Whether this file's thumbnail can be fetched, derived from the thumbnail columns.
+    pub fn thumbnail_state(&self) -> ThumbnailStatus {
+        if self.thumbnail_path.is_some() {
+            return ThumbnailStatus::Ready;
+        }
+
+        match ThumbnailStatus::from_column(self.thumbnail_status.as_deref()) {
+            // A thumbnail recorded as ready but without a path can't be served
+            ThumbnailStatus::Ready => ThumbnailStatus::None,
+            status => status,
+        }
+    }
+}
+
+/// Broad grouping of files derived from their MIME type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Image,
+    Video,
+    Audio,
+    Document,
+    Archive,
+    Other,
+}
+
+/// Filters and pagination accepted by `GET /files`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ListQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<Category>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    /// Case-insensitive substring of the original filename.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search: Option<String>,
+    /// IANA timezone (e.g. `America/New_York`) for `uploaded_at_local`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tz: Option<String>,
+    /// Comma-separated `FileResponse` keys to return, e.g. `id,original_filename,size`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<String>,
+    /// Comma-separated `field[:asc|desc]` pairs, e.g. `file_size:desc,original_filename:asc`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MaintenanceRequest {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MaintenanceResponse {
+    pub maintenance: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackfillResponse {
+    pub scanned: u64,
+    pub updated: u64,
+    pub failed: u64,
+}
+
+/// Query parameters accepted by `GET /admin/logs/recent`.
+#[derive(Debug, Deserialize)]
+pub struct RecentLogsQuery {
+    pub lines: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecentLogsResponse {
+    pub lines: Vec<String>,
+}
+
+/// Per-backend totals computed from the `files` table.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackendUsage {
+    pub storage_type: String,
+    pub file_count: i64,
+    pub total_bytes: i64,
+}
+
+/// Query parameters accepted by `GET /admin/stats/storage`.
+#[derive(Debug, Deserialize)]
+pub struct StorageStatsQuery {
+    /// Also ask the active backend for its own usage figure (may be slow for S3).
+    #[serde(default)]
+    pub include_backend: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StorageStatsResponse {
+    pub backends: Vec<BackendUsage>,
+    pub backend_reported_bytes: Option<u64>,
+}
+
+/// Query parameters accepted by `GET /files/{id}/download`.
+#[derive(Debug, Default, Deserialize)]
+pub struct DownloadQuery {
+    /// Recompute the checksum before serving; defaults to `Config::verify_downloads`.
+    pub verify: Option<bool>,
+    /// Serve the file with this `Content-Type` instead of the stored one.
+    pub content_type: Option<String>,
+    /// Transcode `text/*` files to this charset (e.g. `utf-8`).
+    pub charset: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyResponse {
+    pub valid: bool,
+    pub expected: Option<String>,
+    pub actual: String,
+}
+
+/// Query parameters accepted by `GET /files/{id}/convert`.
+#[derive(Debug, Deserialize)]
+pub struct ConvertQuery {
+    pub format: String,
+    pub quality: Option<u8>,
+}
+
+/// Query parameters accepted by `GET /files/{id}/qr`.
+#[derive(Debug, Deserialize)]
+pub struct QrQuery {
+    /// Edge length in pixels; clamped to 64–1024, default 300.
+    pub size: Option<u32>,
+}
+
+/// Query parameters accepted by `GET /admin/files/broken`.
+#[derive(Debug, Deserialize)]
+pub struct BrokenFilesQuery {
+    pub limit: Option<usize>,
+}
+
+/// A file whose database row exists but whose storage object is missing.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BrokenFile {
+    pub id: Uuid,
+    pub original_filename: String,
+    pub file_path: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BrokenFilesResponse {
+    pub scanned: u64,
+    pub broken: Vec<BrokenFile>,
+}
+
+/// Kind of file access recorded in `access_log.action`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessAction {
+    Download,
+    View,
+    Delete,
+}
+
+impl AccessAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccessAction::Download => "download",
+            AccessAction::View => "view",
+            AccessAction::Delete => "delete",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct AccessLogEntry {
+    pub id: i64,
+    pub file_id: Uuid,
+    pub action: String,
+    pub requester_ip: Option<String>,
+    pub accessed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccessLogQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// Everything a download manager needs to fetch and verify a file.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileManifest {
+    pub id: Uuid,
+    pub filename: String,
+    pub download_url: String,
+    pub size: i64,
+    pub mime_type: String,
+    /// SHA-256 hex digest, or `blake3:`-prefixed BLAKE3 digest.
+    pub checksum: Option<String>,
+    /// MD5 hex digest; `None` for files uploaded before it was recorded.
+    pub md5: Option<String>,
+    /// `ETag` that `GET /files/{id}/download` serves the file with, for
+    /// `If-None-Match`; `None` for files without a checksum.
+    pub etag: Option<String>,
+}
+
+/// Body of `POST /files/download-manifest`.
+#[derive(Debug, Deserialize)]
+pub struct DownloadManifestRequest {
+    pub ids: Vec<Uuid>,
+}
+
+/// A file's manifest, on its own or in a bulk download manifest.
+/// `download_url` is a presigned storage URL when the backend supports one.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DownloadManifestEntry {
+    #[serde(flatten)]
+    pub manifest: FileManifest,
+    pub presigned: bool,
+    /// When a presigned `download_url` stops working.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Download URLs and verification data for a batch of files, so clients can
+/// fetch them concurrently instead of requesting a server-built archive.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DownloadManifest {
+    /// In the order requested, without duplicates.
+    pub files: Vec<DownloadManifestEntry>,
+    /// Requested ids that don't exist or have expired.
+    pub missing: Vec<Uuid>,
+}
+
+/// Body of `POST /uploads`, opening a resumable upload session.
+#[derive(Debug, Deserialize)]
+pub struct CreateUploadSession {
+    pub filename: String,
+    /// Total size of the file in bytes.
+    pub size: i64,
+    /// Defaults to `Config::default_mime_type`.
+    pub mime_type: Option<String>,
+    /// Stored filename, as with the multipart `filename` field.
+    pub custom_filename: Option<String>,
+}
+
+/// A resumable upload in progress, from `upload_sessions`.
+#[derive(Debug, FromRow)]
+pub struct UploadSession {
+    pub id: Uuid,
+    pub original_filename: String,
+    pub mime_type: String,
+    pub custom_filename: Option<String>,
+    pub total_size: i64,
+    /// Bytes received so far; the next chunk must start here.
+    pub received: i64,
+    /// Storage keys of the accepted chunks, in byte order.
+    pub chunk_keys: Vec<String>,
+    /// Set while a finalize attempt holds the session.
+    pub finalizing_until: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Progress of a resumable upload.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadSessionStatus {
+    pub id: Uuid,
+    /// Where to `PATCH` the remaining chunks.
+    pub url: String,
+    pub size: i64,
+    pub received: i64,
+    /// When the session is discarded unless another chunk arrives.
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Where to fetch a file's bytes from: a presigned storage URL when the
+/// backend supports one, otherwise the regular download endpoint.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PresignedUrl {
+    pub url: String,
+    /// When `url` stops working; `None` for the non-expiring download endpoint.
+    pub expires_at: Option<DateTime<Utc>>,
+    pub presigned: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub size_bytes: u64,
+    pub max_bytes: u64,
+    pub ttl_seconds: Option<u64>,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CachePurgeResponse {
+    pub purged: usize,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DeleteQuery {
+    /// Report what would be removed without deleting anything.
+    pub dry_run: Option<bool>,
+}
+
+/// What a delete would remove, returned for `?dry_run=true`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeletePreview {
+    pub files: Vec<DeletePreviewItem>,
+    pub bytes_reclaimed: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeletePreviewItem {
+    pub id: Uuid,
+    pub original_filename: String,
+    pub storage_objects: Vec<String>,
+    pub size: i64,
+}
+
+/// Where a file lives in the storage backend, for debugging and migrations.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StorageDetails {
+    pub id: Uuid,
+    pub storage_type: String,
+    pub location: String,
+    pub key: String,
+    pub exists: bool,
+    /// Size reported by the backend; null when the object is missing.
+    pub stored_size: Option<u64>,
+    /// Size recorded in the database at upload time.
+    pub recorded_size: i64,
+    pub thumbnail_key: Option<String>,
+}
+
+/// Query parameters accepted by `GET /files/{id}`.
+#[derive(Debug, Default, Deserialize)]
+pub struct FileQuery {
+    /// IANA timezone (e.g. `America/New_York`) for `uploaded_at_local`.
+    pub tz: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image_file(thumbnails: Option<serde_json::Value>, thumbnail_path: Option<&str>) -> File {
+        serde_json::from_value(serde_json::json!({
+            "id": Uuid::nil(),
+            "filename": "days.png",
+            "original_filename": "days.png",
+            "file_path": "uploads/files/days.png",
+            "file_size": 37634,
+            "mime_type": "image/png",
+            "storage_type": "local",
+            "flagged": false,
+            "gzip_precompressed": false,
+            "thumbnails": thumbnails,
+            "thumbnail_path": thumbnail_path,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn thumbnail_for_returns_the_requested_size() {
+        let file = image_file(
+            Some(serde_json::json!({ "small": "t/s.jpg", "medium": "t/m.jpg", "large": "t/l.jpg" })),
+            Some("t/m.jpg"),
+        );
+        assert_eq!(file.thumbnail_for(ThumbnailSize::Small).as_deref(), Some("t/s.jpg"));
+        assert_eq!(file.thumbnail_for(ThumbnailSize::Large).as_deref(), Some("t/l.jpg"));
+    }
+
+    #[test]
+    fn thumbnail_for_falls_back_to_the_nearest_size() {
+        let file = image_file(Some(serde_json::json!({ "small": "t/s.jpg", "large": "t/l.jpg" })), None);
+        // Equally near: the larger neighbour wins
+        assert_eq!(file.thumbnail_for(ThumbnailSize::Medium).as_deref(), Some("t/l.jpg"));
+
+        let file = image_file(Some(serde_json::json!({ "small": "t/s.jpg" })), None);
+        assert_eq!(file.thumbnail_for(ThumbnailSize::Large).as_deref(), Some("t/s.jpg"));
+    }
+
+    #[test]
+    fn thumbnail_for_uses_thumbnail_path_for_older_files() {
+        let file = image_file(None, Some("t/legacy.jpg"));
+        assert_eq!(file.thumbnail_for(ThumbnailSize::Small).as_deref(), Some("t/legacy.jpg"));
+
+        assert_eq!(image_file(None, None).thumbnail_for(ThumbnailSize::Medium), None);
+    }
 }
@@ -0,0 +1,326 @@
+use sqlx::{Postgres, QueryBuilder};
+
+use crate::{
+    config::SortDirection,
+    error::AppError,
+    models::{Category, ListQuery},
+    utils::{ARCHIVE_MIME_TYPES, DOCUMENT_MIME_TYPES},
+};
+
+/// Default and maximum page sizes for file listings.
+pub const DEFAULT_LIMIT: i64 = 50;
+pub const MAX_LIMIT: i64 = 200;
+
+/// Columns `?sort=` may order by.
+const SORTABLE_FIELDS: &[&str] = &[
+    "uploaded_at",
+    "updated_at",
+    "file_size",
+    "original_filename",
+    "filename",
+    "mime_type",
+];
+
+/// SQL expression matching `utils::mime_category`'s normalization:
+/// parameters stripped, whitespace trimmed, lowercased.
+const MIME_ESSENCE: &str = "lower(trim(split_part(mime_type, ';', 1)))";
+
+/// Validated `(limit, offset)` for `query`, applying defaults.
+pub fn page(query: &ListQuery) -> Result<(i64, i64), AppError> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    let offset = query.offset.unwrap_or(0);
+
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(AppError::BadRequest(format!(
+            "limit must be between 1 and {}",
+            MAX_LIMIT
+        )));
+    }
+    if offset < 0 {
+        return Err(AppError::BadRequest("offset must not be negative".into()));
+    }
+
+    Ok((limit, offset))
+}
+
+/// Parse `?sort=` into validated `(column, direction)` pairs, defaulting to
+/// newest first. Fields without a direction use `default_direction`.
+pub fn sort_order(
+    query: &ListQuery,
+    default_direction: SortDirection,
+) -> Result<Vec<(&'static str, SortDirection)>, AppError> {
+    let Some(sort) = query.sort.as_deref().filter(|s| !s.trim().is_empty()) else {
+        return Ok(vec![("uploaded_at", SortDirection::Desc)]);
+    };
+
+    sort.split(',')
+        .map(|part| {
+            let (field, direction) = match part.trim().split_once(':') {
+                Some((field, direction)) => (field, direction.parse::<SortDirection>().map_err(AppError::BadRequest)?),
+                None => (part.trim(), default_direction),
+            };
+
+            let column = SORTABLE_FIELDS
+                .iter()
+                .find(|allowed| **allowed == field)
+                .ok_or_else(|| AppError::BadRequest(format!(
+                    "Cannot sort by '{}'; allowed fields: {}",
+                    field,
+                    SORTABLE_FIELDS.join(", ")
+                )))?;
+
+            Ok((*column, direction))
+        })
+        .collect()
+}
+
+/// Build a paginated `SELECT` over `files` for the validated `query`.
+/// Expired files are never returned; see `File::is_expired`.
+pub fn select_files(
+    query: &ListQuery,
+    default_direction: SortDirection,
+    retention_days: u32,
+) -> Result<QueryBuilder<'static, Postgres>, AppError> {
+    let (limit, offset) = page(query)?;
+
+    let mut builder = QueryBuilder::new("SELECT * FROM files");
+    push_filters(&mut builder, query, retention_days);
+    push_order_by(&mut builder, &sort_order(query, default_direction)?);
+    builder.push(" LIMIT ").push_bind(limit);
+    builder.push(" OFFSET ").push_bind(offset);
+
+    Ok(builder)
+}
+
+/// Build an unpaginated `SELECT` over `files` for streaming every match of `query`.
+pub fn select_all_files(
+    query: &ListQuery,
+    default_direction: SortDirection,
+    retention_days: u32,
+) -> Result<QueryBuilder<'static, Postgres>, AppError> {
+    let mut builder = QueryBuilder::new("SELECT * FROM files");
+    push_filters(&mut builder, query, retention_days);
+    push_order_by(&mut builder, &sort_order(query, default_direction)?);
+    Ok(builder)
+}
+
+/// Build a `SELECT COUNT(*)` over `files` sharing the filters of `select_files`.
+pub fn count_files(query: &ListQuery, retention_days: u32) -> QueryBuilder<'static, Postgres> {
+    let mut builder = QueryBuilder::new("SELECT COUNT(*) FROM files");
+    push_filters(&mut builder, query, retention_days);
+    builder
+}
+
+/// Build an RFC 5988 `Link` header value with first/prev/next/last relations
+/// for offset pagination. Filters on `query` are carried over to every link.
+pub fn link_header(base_url: &str, query: &ListQuery, total: i64) -> Result<String, AppError> {
+    let (limit, offset) = page(query)?;
+    let last_offset = if total > 0 { ((total - 1) / limit) * limit } else { 0 };
+
+    let mut relations = vec![("first", 0)];
+    if offset > 0 {
+        relations.push(("prev", (offset - limit).max(0)));
+    }
+    if offset + limit < total {
+        relations.push(("next", offset + limit));
+    }
+    relations.push(("last", last_offset));
+
+    let links = relations
+        .into_iter()
+        .map(|(rel, offset)| {
+            let page_query = ListQuery {
+                limit: Some(limit),
+                offset: Some(offset),
+                ..query.clone()
+            };
+            let params = serde_urlencoded::to_string(&page_query)
+                .map_err(|e| AppError::InternalServerError(format!("Failed to build link: {}", e)))?;
+            Ok(format!("<{}?{}>; rel=\"{}\"", base_url, params, rel))
+        })
+        .collect::<Result<Vec<_>, AppError>>()?;
+
+    Ok(links.join(", "))
+}
+
+/// Append the `WHERE` clause for every filter set on `query`, always
+/// excluding expired files. All user-provided values are bound as parameters,
+/// never interpolated.
+fn push_filters(builder: &mut QueryBuilder<'static, Postgres>, query: &ListQuery, retention_days: u32) {
+    builder.push(" WHERE (expires_at IS NULL OR expires_at > NOW())");
+    if retention_days > 0 {
+        builder
+            .push(" AND (uploaded_at IS NULL OR uploaded_at > NOW() - make_interval(days => ")
+            .push_bind(i32::try_from(retention_days).unwrap_or(i32::MAX))
+            .push("))");
+    }
+
+    if let Some(category) = query.category {
+        builder.push(" AND ");
+        push_category(builder, category);
+    }
+
+    if let Some(mime_type) = &query.mime_type {
+        builder
+            .push(" AND ")
+            .push(MIME_ESSENCE)
+            .push(" = ")
+            .push_bind(mime_type.trim().to_lowercase());
+    }
+
+    if let Some(search) = query.search.as_deref().filter(|s| !s.trim().is_empty()) {
+        // Escape LIKE wildcards so the search term matches literally
+        let pattern = search
+            .trim()
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        builder
+            .push(" AND original_filename ILIKE ")
+            .push_bind(format!("%{}%", pattern));
+    }
+}
+
+/// Append `ORDER BY` for the validated sort columns, tie-breaking on `id`
+/// so pages stay stable when sort values repeat.
+fn push_order_by(builder: &mut QueryBuilder<'static, Postgres>, order: &[(&'static str, SortDirection)]) {
+    builder.push(" ORDER BY ");
+    for (column, direction) in order {
+        builder.push(format!("{} {}, ", column, direction.as_sql()));
+    }
+    builder.push("id ASC");
+}
+
+/// Append a condition selecting files of the given category.
+fn push_category(builder: &mut QueryBuilder<'static, Postgres>, category: Category) {
+    match category {
+        Category::Image => {
+            builder.push(format!("{} LIKE 'image/%'", MIME_ESSENCE));
+        }
+        Category::Video => {
+            builder.push(format!("{} LIKE 'video/%'", MIME_ESSENCE));
+        }
+        Category::Audio => {
+            builder.push(format!("{} LIKE 'audio/%'", MIME_ESSENCE));
+        }
+        Category::Archive => {
+            builder
+                .push(format!("{} = ANY(", MIME_ESSENCE))
+                .push_bind(ARCHIVE_MIME_TYPES.to_vec())
+                .push(")");
+        }
+        Category::Document => {
+            builder
+                .push(format!("({} LIKE 'text/%' OR {} = ANY(", MIME_ESSENCE, MIME_ESSENCE))
+                .push_bind(DOCUMENT_MIME_TYPES.to_vec())
+                .push("))");
+        }
+        Category::Other => {
+            // Anything not matched by one of the named categories
+            builder.push("NOT (");
+            for (i, named) in [
+                Category::Image,
+                Category::Video,
+                Category::Audio,
+                Category::Archive,
+                Category::Document,
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                if i > 0 {
+                    builder.push(" OR ");
+                }
+                push_category(builder, named);
+            }
+            builder.push(")");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(sort: &str) -> ListQuery {
+        ListQuery { sort: Some(sort.to_string()), ..Default::default() }
+    }
+
+    #[test]
+    fn sort_order_defaults_to_newest_first() {
+        let order = sort_order(&ListQuery::default(), SortDirection::Asc).unwrap();
+        assert_eq!(order, vec![("uploaded_at", SortDirection::Desc)]);
+
+        let order = sort_order(&sorted("  "), SortDirection::Asc).unwrap();
+        assert_eq!(order, vec![("uploaded_at", SortDirection::Desc)]);
+    }
+
+    #[test]
+    fn sort_order_parses_fields_and_directions() {
+        let order = sort_order(&sorted("file_size:desc, original_filename:ASC,mime_type"), SortDirection::Asc).unwrap();
+        assert_eq!(
+            order,
+            vec![
+                ("file_size", SortDirection::Desc),
+                ("original_filename", SortDirection::Asc),
+                ("mime_type", SortDirection::Asc),
+            ]
+        );
+
+        let order = sort_order(&sorted("filename"), SortDirection::Desc).unwrap();
+        assert_eq!(order, vec![("filename", SortDirection::Desc)]);
+    }
+
+    #[test]
+    fn sort_order_rejects_unknown_fields_and_directions() {
+        assert!(matches!(sort_order(&sorted("checksum"), SortDirection::Asc), Err(AppError::BadRequest(_))));
+        assert!(matches!(sort_order(&sorted("id; DROP TABLE files"), SortDirection::Asc), Err(AppError::BadRequest(_))));
+        assert!(matches!(sort_order(&sorted("file_size:sideways"), SortDirection::Asc), Err(AppError::BadRequest(_))));
+    }
+
+    #[test]
+    fn link_header_links_every_page_and_keeps_filters() {
+        let query = ListQuery {
+            search: Some("report".to_string()),
+            limit: Some(10),
+            offset: Some(10),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            link_header("/files", &query, 35).unwrap(),
+            "</files?search=report&limit=10&offset=0>; rel=\"first\", \
+             </files?search=report&limit=10&offset=0>; rel=\"prev\", \
+             </files?search=report&limit=10&offset=20>; rel=\"next\", \
+             </files?search=report&limit=10&offset=30>; rel=\"last\""
+        );
+    }
+
+    #[test]
+    fn link_header_omits_prev_and_next_at_the_edges() {
+        let query = ListQuery { limit: Some(50), ..Default::default() };
+        assert_eq!(
+            link_header("/files", &query, 0).unwrap(),
+            "</files?limit=50&offset=0>; rel=\"first\", </files?limit=50&offset=0>; rel=\"last\""
+        );
+    }
+
+    #[test]
+    fn category_filter_matches_mime_category() {
+        let query = ListQuery { category: Some(Category::Image), ..Default::default() };
+        let builder = count_files(&query, 0);
+        assert_eq!(
+            builder.sql(),
+            "SELECT COUNT(*) FROM files WHERE (expires_at IS NULL OR expires_at > NOW()) \
+             AND lower(trim(split_part(mime_type, ';', 1))) LIKE 'image/%'"
+        );
+
+        let query = ListQuery { category: Some(Category::Other), ..Default::default() };
+        let builder = count_files(&query, 0);
+        let sql = builder.sql();
+        assert!(sql.contains("AND NOT ("), "{}", sql);
+        for named in ["'image/%'", "'video/%'", "'audio/%'", "'text/%'"] {
+            assert!(sql.contains(named), "{}", sql);
+        }
+    }
+}
@@ -1,6 +1,13 @@
+use std::sync::{Arc, atomic::AtomicBool};
+
+use image::RgbaImage;
 use sqlx::PgPool;
 use crate::storage::StorageBackend;
 use crate::config::Config;
+use crate::logs::LogBuffer;
+use crate::limiter::DownloadLimiter;
+use crate::cache::DownloadCache;
+use crate::breaker::CircuitBreaker;
 
 /// Central application state shared across all Axum handlers.
 #[derive(Clone)]
@@ -13,4 +20,47 @@ pub struct AppState {
     
     /// Application configuration loaded from environment variables or `.env`.
     pub config: Config,
-}
\ No newline at end of file
+
+    /// Maintenance flag; while set, mutating routes are rejected with `503`.
+    pub maintenance: Arc<AtomicBool>,
+
+    /// Set once the storage backend has accepted a probe write; gates `/health/ready`.
+    pub ready: Arc<AtomicBool>,
+
+    /// Ring buffer of recent log lines, exposed through the admin API.
+    pub logs: LogBuffer,
+
+    /// Watermark overlaid on served thumbnails, loaded once at startup.
+    pub watermark: Option<Arc<RgbaImage>>,
+
+    /// Per-file download concurrency cap, if configured.
+    pub download_limiter: Option<DownloadLimiter>,
+
+    /// Local disk cache of downloaded S3 objects, if configured.
+    pub download_cache: Option<DownloadCache>,
+
+    /// Circuit breaker that fast-fails uploads while storage writes are failing.
+    pub upload_breaker: Option<CircuitBreaker>,
+}
+
+#[cfg(test)]
+impl AppState {
+    /// State for `Config::for_tests(vars)`; the pool only connects once a query runs.
+    pub(crate) async fn for_tests(vars: &[(&str, &str)]) -> Self {
+        let config = Config::for_tests(vars);
+        AppState {
+            pool: sqlx::postgres::PgPoolOptions::new()
+                .connect_lazy(&config.database_url)
+                .expect("Invalid test database URL"),
+            storage: crate::storage::init_storage(&config).await,
+            logs: LogBuffer::new(config.log_buffer_lines),
+            config,
+            maintenance: Arc::new(AtomicBool::new(false)),
+            ready: Arc::new(AtomicBool::new(false)),
+            watermark: None,
+            download_limiter: None,
+            download_cache: None,
+            upload_breaker: None,
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use azure_core::StatusCode;
+use azure_storage::StorageCredentials;
+use azure_storage_blobs::{
+    blob::{BlobBlockType, BlockList, BlobSasPermissions},
+    prelude::{BlobClient, BlockId, ClientBuilder, ContainerClient},
+};
+use bytes::{Bytes, BytesMut};
+use futures::{StreamExt, TryStreamExt};
+use time::{Duration, OffsetDateTime};
+use tracing::info;
+
+use crate::{config::Config, storage::{Storage, StorageError, StoredObject, UploadStream}};
+
+/// Size of each block staged by `upload_stream`.
+const BLOCK_SIZE: usize = 8 * 1024 * 1024;
+
+/// How long the source URL handed to a server-side copy stays valid.
+const COPY_SOURCE_EXPIRY: Duration = Duration::minutes(15);
+
+// Azure Blob Storage backend
+#[derive(Clone)]
+pub struct AzureStorage {
+    container: ContainerClient, // Client for the configured container
+    account: String,            // Storage account name
+    container_name: String,     // Blob container name
+    prefix: String,             // Key prefix for every blob this service writes
+}
+
+impl AzureStorage {
+    /// Initialize the container client and ensure the container exists
+    pub async fn new(config: &Config) -> Self {
+        let credentials = StorageCredentials::access_key(
+            config.azure_account.clone(),
+            config.azure_key.clone(),
+        );
+        let container = ClientBuilder::new(config.azure_account.clone(), credentials)
+            .container_client(&config.azure_container);
+
+        // Ensure container exists
+        match container.exists().await {
+            Ok(true) => info!("Container {} already exists", config.azure_container),
+            Ok(false) => match container.create().await {
+                Ok(_) => info!("Container {} created successfully", config.azure_container),
+                Err(e) => tracing::error!("Container {} does not exist and cannot be created: {}", config.azure_container, e),
+            },
+            Err(e) => tracing::warn!("Could not check container {}: {}", config.azure_container, e),
+        }
+
+        Self {
+            container,
+            account: config.azure_account.clone(),
+            container_name: config.azure_container.clone(),
+            prefix: config.storage_prefix.clone(),
+        }
+    }
+
+    /// Full blob name for a path, including the configured prefix
+    fn key(&self, file_path: &str) -> String {
+        format!("{}{}", self.prefix, file_path)
+    }
+
+    /// Stage one block of a streamed upload and record it for the block list
+    async fn put_block(&self, blob: &BlobClient, blocks: &mut Vec<BlobBlockType>, content: Bytes) -> Result<(), StorageError> {
+        // Block ids must all have the same length within a blob
+        let block_id = BlockId::new(format!("{:08}", blocks.len()));
+        blob.put_block(block_id.clone(), content)
+            .await
+            .map_err(|e| StorageError::UploadError(e.to_string()))?;
+        blocks.push(BlobBlockType::new_uncommitted(block_id));
+        Ok(())
+    }
+}
+
+/// Whether an Azure error is a 404 for the requested blob
+fn is_not_found(e: &azure_core::Error) -> bool {
+    e.as_http_error().is_some_and(|e| e.status() == StatusCode::NotFound)
+}
+
+#[async_trait]
+impl Storage for AzureStorage {
+
+    /// Uploads content as a block blob
+    async fn upload(&self, file_path: &str, content: Bytes) -> Result<String, StorageError> {
+        self.container
+            .blob_client(self.key(file_path))
+            .put_block_blob(content)
+            .await
+            .map_err(|e| StorageError::UploadError(e.to_string()))?;
+
+        Ok(self.stored_path(file_path))
+    }
+
+    /// Uploads the stream as a block blob, one block at a time, so only a
+    /// single block is held in memory
+    async fn upload_stream(&self, file_path: &str, mut stream: UploadStream<'_>) -> Result<u64, StorageError> {
+        let blob = self.container.blob_client(self.key(file_path));
+        let mut blocks = Vec::new();
+        let mut buffer = BytesMut::new();
+        let mut total = 0u64;
+
+        while let Some(chunk) = stream.try_next().await? {
+            total += chunk.len() as u64;
+            buffer.extend_from_slice(&chunk);
+            while buffer.len() >= BLOCK_SIZE {
+                let block = buffer.split_to(BLOCK_SIZE).freeze();
+                self.put_block(&blob, &mut blocks, block).await?;
+            }
+        }
+        if !buffer.is_empty() || blocks.is_empty() {
+            self.put_block(&blob, &mut blocks, buffer.freeze()).await?;
+        }
+
+        // Uncommitted blocks are discarded by Azure if the list is never committed
+        blob.put_block_list(BlockList { blocks })
+            .await
+            .map_err(|e| StorageError::UploadError(e.to_string()))?;
+
+        info!("Streamed {} bytes to azure: {}", total, file_path);
+
+        Ok(total)
+    }
+
+    /// Downloads a blob's content
+    async fn download(&self, file_path: &str) -> Result<Bytes, StorageError> {
+        let content = self.container
+            .blob_client(self.key(file_path))
+            .get_content()
+            .await
+            .map_err(|e| {
+                if is_not_found(&e) {
+                    StorageError::NotFound(file_path.to_string())
+                } else {
+                    StorageError::IoError(std::io::Error::other(e.to_string()))
+                }
+            })?;
+
+        Ok(Bytes::from(content))
+    }
+
+    /// Deletes a blob; a blob that is already gone counts as deleted
+    async fn delete(&self, file_path: &str) -> Result<(), StorageError> {
+        match self.container.blob_client(self.key(file_path)).delete().await {
+            Ok(_) => {}
+            Err(e) if is_not_found(&e) => return Ok(()),
+            Err(e) => return Err(StorageError::DeleteError(e.to_string())),
+        }
+
+        info!("File deleted sucessfully from azure: {}", file_path);
+        Ok(())
+    }
+
+    /// Checks whether a blob exists
+    async fn exists(&self, file_path: &str) -> Result<bool, StorageError> {
+        self.container
+            .blob_client(self.key(file_path))
+            .exists()
+            .await
+            .map_err(|e| StorageError::IoError(std::io::Error::other(e.to_string())))
+    }
+
+    /// Returns a blob's size from its properties
+    async fn object_size(&self, file_path: &str) -> Result<Option<u64>, StorageError> {
+        match self.container.blob_client(self.key(file_path)).get_properties().await {
+            Ok(properties) => Ok(Some(properties.blob.properties.content_length)),
+            Err(e) if is_not_found(&e) => Ok(None),
+            Err(e) => Err(StorageError::IoError(std::io::Error::other(e.to_string()))),
+        }
+    }
+
+    /// Blob storage has no rename, so the blob is copied server-side to the
+    /// new name and the original deleted. The copy source is read through a
+    /// short-lived SAS URL, which Copy Blob From URL requires for private blobs.
+    async fn rename(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let source = self.container.blob_client(self.key(from));
+        let permissions = BlobSasPermissions { read: true, ..Default::default() };
+        let source_url = source
+            .shared_access_signature(permissions, OffsetDateTime::now_utc() + COPY_SOURCE_EXPIRY)
+            .await
+            .and_then(|signature| source.generate_signed_blob_url(&signature))
+            .map_err(|e| StorageError::UploadError(e.to_string()))?;
+
+        self.container
+            .blob_client(self.key(to))
+            .copy_from_url(source_url)
+            .await
+            .map_err(|e| {
+                if is_not_found(&e) {
+                    StorageError::NotFound(from.to_string())
+                } else {
+                    StorageError::UploadError(e.to_string())
+                }
+            })?;
+
+        self.delete(from).await
+    }
+
+    /// Lists blobs under the prefix, with names relative to the configured storage prefix
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, StorageError> {
+        let mut pages = self.container
+            .list_blobs()
+            .prefix(self.key(prefix))
+            .into_stream();
+
+        let mut objects = Vec::new();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| StorageError::ListError(e.to_string()))?;
+            for blob in page.blobs.blobs() {
+                objects.push(StoredObject {
+                    key: blob.name.strip_prefix(self.prefix.as_str()).unwrap_or(&blob.name).to_string(),
+                    size: blob.properties.content_length,
+                    last_modified: SystemTime::from(blob.properties.last_modified),
+                });
+            }
+        }
+
+        Ok(objects)
+    }
+
+    fn stored_path(&self, file_path: &str) -> String {
+        format!("azure://{}", file_path)
+    }
+
+    fn location(&self) -> String {
+        format!("azure://{}/{}", self.account, self.container_name)
+    }
+
+    fn object_key(&self, file_path: &str) -> String {
+        self.key(file_path)
+    }
+
+    /// Sums the size of every blob under the configured prefix
+    async fn usage(&self) -> Result<Option<u64>, StorageError> {
+        let objects = self.list("").await?;
+        Ok(Some(objects.iter().map(|object| object.size).sum()))
+    }
+}
@@ -1,28 +1,33 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use bytes::Bytes;
-use super::{Storage, StorageError};
+use super::{Storage, StorageError, StoredObject, UploadStream};
 use async_trait::async_trait;
 use tokio::{fs, io::AsyncWriteExt};
+use tokio_util::io::StreamReader;
 
 // Local filesystem storage
 #[derive(Clone)]
 pub struct LocalStorage{
     base_path: String, // Base directory where files will be stored
+    prefix: String,    // Key prefix applied beneath the base directory
 }
 
 impl LocalStorage {
-    pub async fn new(base_path: &str) -> Self {
+    pub async fn new(base_path: &str, prefix: &str) -> Self {
         /// Creates a new LocalStorage instance and ensures necessary directories exist
-        fs::create_dir_all(base_path).await.expect("Failed to create uploads directory");
-        fs::create_dir_all(format!("{}/files",base_path)).await.expect("Failed to create files directory");
-        fs::create_dir_all(format!("{}/thumbnails",base_path)).await.expect("Failed to create thumbnails directory");
+        let root = format!("{}/{}", base_path, prefix);
+        fs::create_dir_all(&root).await.expect("Failed to create uploads directory");
+        fs::create_dir_all(format!("{}files",root)).await.expect("Failed to create files directory");
+        fs::create_dir_all(format!("{}thumbnails",root)).await.expect("Failed to create thumbnails directory");
         Self {
             base_path: base_path.to_string(),
+            prefix: prefix.to_string(),
         }
     }
-    /// Returns the full path of a file relative to the base directory
+    /// Returns the full path of a file relative to the base directory,
+    /// including the configured key prefix
     fn get_full_path(&self, file_path: &str) -> String {
-        format!("{}/{}", self.base_path, file_path)
+        format!("{}/{}{}", self.base_path, self.prefix, file_path)
     }
 }
 
@@ -46,7 +51,37 @@ impl Storage for LocalStorage {
 
         tracing::info!("Saved file at {:?}", full_path);
 
-        Ok(full_path)
+        // The prefix is applied transparently, so the returned path omits it
+        Ok(self.stored_path(file_path))
+    }
+
+    /// Streams content into a file on the local filesystem
+    async fn upload_stream(&self, file_path: &str, stream: UploadStream<'_>)
+    -> Result<u64, StorageError> {
+
+        let full_path = self.get_full_path(file_path);
+
+        // Ensure parent directories exist
+        if let Some(parent) = Path::new(&full_path).parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        // Copy chunks to disk as they arrive, removing the partial file on failure
+        let mut file = fs::File::create(&full_path).await?;
+        let mut reader = StreamReader::new(stream);
+        let written = match tokio::io::copy(&mut reader, &mut file).await {
+            Ok(written) => written,
+            Err(e) => {
+                drop(file);
+                let _ = fs::remove_file(&full_path).await;
+                return Err(e.into());
+            }
+        };
+        file.flush().await?;
+
+        tracing::info!("Streamed {} bytes to {:?}", written, full_path);
+
+        Ok(written)
     }
 
     /// Downloads a file from local filesystem
@@ -74,4 +109,129 @@ impl Storage for LocalStorage {
         }
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Checks whether a file exists on the local filesystem
+    async fn exists(&self, file_path: &str) -> Result<bool, StorageError> {
+        let full_path = self.get_full_path(file_path);
+        Ok(fs::try_exists(&full_path).await?)
+    }
+
+    /// Returns the size of a file on the local filesystem
+    async fn object_size(&self, file_path: &str) -> Result<Option<u64>, StorageError> {
+        match fs::metadata(self.get_full_path(file_path)).await {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(StorageError::IoError(e)),
+        }
+    }
+
+    /// Moves a file on the local filesystem
+    async fn rename(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let from_path = self.get_full_path(from);
+        if !Path::new(&from_path).exists() {
+            return Err(StorageError::NotFound(from.to_string()));
+        }
+
+        let to_path = self.get_full_path(to);
+        if let Some(parent) = Path::new(&to_path).parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        fs::rename(&from_path, &to_path).await?;
+        Ok(())
+    }
+
+    /// Lists files under a directory prefix, walking subdirectories
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, StorageError> {
+        let root = PathBuf::from(format!("{}/{}", self.base_path, self.prefix));
+        let mut pending = vec![PathBuf::from(self.get_full_path(prefix))];
+        let mut objects = Vec::new();
+
+        while let Some(dir) = pending.pop() {
+            let mut entries = match fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(StorageError::IoError(e)),
+            };
+
+            while let Some(entry) = entries.next_entry().await? {
+                let metadata = entry.metadata().await?;
+                if metadata.is_dir() {
+                    pending.push(entry.path());
+                } else if let Ok(relative) = entry.path().strip_prefix(&root) {
+                    objects.push(StoredObject {
+                        key: relative.to_string_lossy().into_owned(),
+                        size: metadata.len(),
+                        last_modified: metadata.modified()?,
+                    });
+                }
+            }
+        }
+
+        Ok(objects)
+    }
+
+    /// Returns the free space on the filesystem holding the base directory
+    async fn available_space(&self) -> Result<Option<u64>, StorageError> {
+        let base_path = self.base_path.clone();
+        let available = tokio::task::spawn_blocking(move || fs2::available_space(base_path))
+            .await
+            .map_err(|e| StorageError::IoError(std::io::Error::other(e)))??;
+        Ok(Some(available))
+    }
+
+    fn stored_path(&self, file_path: &str) -> String {
+        format!("{}/{}", self.base_path, file_path)
+    }
+
+    fn location(&self) -> String {
+        self.base_path.clone()
+    }
+
+    fn object_key(&self, file_path: &str) -> String {
+        self.get_full_path(file_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn prefix_is_applied_to_every_operation() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().to_str().unwrap();
+        let storage = LocalStorage::new(base, "tenant-a/").await;
+        let prefixed = dir.path().join("tenant-a/files/a.txt");
+
+        storage.upload("files/a.txt", Bytes::from_static(b"hello")).await.unwrap();
+        assert_eq!(std::fs::read(&prefixed).unwrap(), b"hello");
+        assert!(!dir.path().join("files/a.txt").exists());
+
+        assert_eq!(storage.download("files/a.txt").await.unwrap(), Bytes::from_static(b"hello"));
+        assert!(storage.exists("files/a.txt").await.unwrap());
+        assert_eq!(storage.object_size("files/a.txt").await.unwrap(), Some(5));
+        assert_eq!(storage.object_key("files/a.txt"), prefixed.to_str().unwrap());
+
+        // Listed keys are relative to the prefix, so they round-trip through the other calls
+        let keys: Vec<String> = storage.list("files/").await.unwrap().into_iter().map(|o| o.key).collect();
+        assert_eq!(keys, vec!["files/a.txt"]);
+
+        storage.delete("files/a.txt").await.unwrap();
+        assert!(!prefixed.exists());
+        assert!(!storage.exists("files/a.txt").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn prefixed_storages_do_not_see_each_other() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().to_str().unwrap();
+        let tenant_a = LocalStorage::new(base, "tenant-a/").await;
+        let tenant_b = LocalStorage::new(base, "tenant-b/").await;
+
+        tenant_a.upload("files/a.txt", Bytes::from_static(b"a")).await.unwrap();
+        assert!(!tenant_b.exists("files/a.txt").await.unwrap());
+        assert!(matches!(tenant_b.download("files/a.txt").await, Err(StorageError::NotFound(_))));
+        assert!(tenant_b.list("files/").await.unwrap().is_empty());
+    }
+}
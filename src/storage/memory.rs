@@ -0,0 +1,103 @@
+use std::{sync::Arc, time::SystemTime};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use dashmap::DashMap;
+
+use super::{Storage, StorageError, StoredObject};
+
+// In-memory storage; contents are lost when the process exits
+#[derive(Clone, Default)]
+pub struct MemoryStorage {
+    objects: Arc<DashMap<String, MemoryObject>>, // Objects keyed by path
+}
+
+#[derive(Clone)]
+struct MemoryObject {
+    content: Bytes,
+    last_modified: SystemTime,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+
+    /// Stores content under the given key
+    async fn upload(&self, file_path: &str, content: Bytes) -> Result<String, StorageError> {
+        self.objects.insert(
+            file_path.to_string(),
+            MemoryObject { content, last_modified: SystemTime::now() },
+        );
+        Ok(self.stored_path(file_path))
+    }
+
+    /// Returns the content stored under the given key
+    async fn download(&self, file_path: &str) -> Result<Bytes, StorageError> {
+        self.objects
+            .get(file_path)
+            .map(|object| object.content.clone())
+            .ok_or_else(|| StorageError::NotFound(file_path.to_string()))
+    }
+
+    /// Removes the given key if present
+    async fn delete(&self, file_path: &str) -> Result<(), StorageError> {
+        self.objects.remove(file_path);
+        Ok(())
+    }
+
+    /// Checks whether the given key is stored
+    async fn exists(&self, file_path: &str) -> Result<bool, StorageError> {
+        Ok(self.objects.contains_key(file_path))
+    }
+
+    /// Returns the size of the content stored under the given key
+    async fn object_size(&self, file_path: &str) -> Result<Option<u64>, StorageError> {
+        Ok(self.objects.get(file_path).map(|object| object.content.len() as u64))
+    }
+
+    /// Moves content to a new key
+    async fn rename(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let (_, object) = self
+            .objects
+            .remove(from)
+            .ok_or_else(|| StorageError::NotFound(from.to_string()))?;
+        self.objects.insert(to.to_string(), object);
+        Ok(())
+    }
+
+    /// Lists stored keys starting with the prefix
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, StorageError> {
+        Ok(self
+            .objects
+            .iter()
+            .filter(|entry| entry.key().starts_with(prefix))
+            .map(|entry| StoredObject {
+                key: entry.key().clone(),
+                size: entry.content.len() as u64,
+                last_modified: entry.last_modified,
+            })
+            .collect())
+    }
+
+    fn stored_path(&self, file_path: &str) -> String {
+        format!("memory://{}", file_path)
+    }
+
+    fn location(&self) -> String {
+        "memory".to_string()
+    }
+
+    fn object_key(&self, file_path: &str) -> String {
+        file_path.to_string()
+    }
+
+    /// Sums the size of every stored object
+    async fn usage(&self) -> Result<Option<u64>, StorageError> {
+        Ok(Some(self.objects.iter().map(|entry| entry.content.len() as u64).sum()))
+    }
+}
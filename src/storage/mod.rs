@@ -1,17 +1,28 @@
-// Submodules for local file system storage and S3 storage
+// Submodules for local file system, S3, Azure and in-memory storage
+mod azure;
 mod local;
+mod memory;
 mod s3;
 
+use std::{pin::Pin, sync::atomic::{AtomicBool, Ordering}, time::{Duration, SystemTime}};
+
 use async_trait::async_trait;
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, TryStreamExt};
+use sqlx::PgPool;
 use thiserror::Error;
-use tracing::info;
+use tracing::{error, info, warn};
+use uuid::Uuid;
 
 use crate::{
-    storage::{local::LocalStorage, s3::S3Storage},
-    config::Config,
+    storage::{azure::AzureStorage, local::LocalStorage, memory::MemoryStorage, s3::S3Storage},
+    config::{Config, StorageKind},
+    utils::storage_relative_path,
 };
 
+/// Key prefix under which staged uploads are written before being committed.
+pub const STAGING_PREFIX: &str = "staging/";
+
 // Storage error types
 #[derive(Debug, Error)]
 pub enum StorageError {
@@ -25,7 +36,24 @@ pub enum StorageError {
     UploadError(String), // Errors during upload to storage
 
     #[error("Delete Error: {0}")]
-    DeleteError(String) // Errors during deletion from storage
+    DeleteError(String), // Errors during deletion from storage
+
+    #[error("List Error: {0}")]
+    ListError(String), // Errors while listing stored objects
+
+    #[error("Presign Error: {0}")]
+    PresignError(String) // Errors while presigning a request
+}
+
+/// Body chunks passed to `Storage::upload_stream`.
+pub type UploadStream<'a> = Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send + 'a>>;
+
+/// An object returned by `Storage::list`.
+#[derive(Debug, Clone)]
+pub struct StoredObject {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: SystemTime,
 }
 
 // Async Storage trait
@@ -35,12 +63,64 @@ pub trait Storage: Send + Sync {
     /// Returns the full path or key of the uploaded file.
     async fn upload(&self, file_path: &str, content: Bytes) -> Result<String, StorageError>;
 
+    /// Upload a file from a stream of chunks without holding it all in memory.
+    /// Returns the number of bytes written. The default implementation buffers
+    /// the stream and calls `upload`.
+    async fn upload_stream(&self, file_path: &str, mut stream: UploadStream<'_>) -> Result<u64, StorageError> {
+        let mut content = BytesMut::new();
+        while let Some(chunk) = stream.try_next().await? {
+            content.extend_from_slice(&chunk);
+        }
+        let size = content.len() as u64;
+        self.upload(file_path, content.freeze()).await?;
+        Ok(size)
+    }
+
     /// Download a file from the storage backend.
     /// Returns the file content as `Bytes`.
     async fn download(&self, file_path: &str) -> Result<Bytes, StorageError>;
 
     /// Delete a file from the storage backend.
     async fn delete(&self, file_path: &str) -> Result<(), StorageError>;
+
+    /// Check whether an object exists in the storage backend.
+    async fn exists(&self, file_path: &str) -> Result<bool, StorageError>;
+
+    /// Size in bytes of an object, or `None` if it doesn't exist.
+    async fn object_size(&self, file_path: &str) -> Result<Option<u64>, StorageError>;
+
+    /// Move an object to a new key, replacing anything already there.
+    async fn rename(&self, from: &str, to: &str) -> Result<(), StorageError>;
+
+    /// List objects whose key starts with `prefix`.
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, StorageError>;
+
+    /// Path recorded in the database for an object, as returned by `upload`.
+    fn stored_path(&self, file_path: &str) -> String;
+
+    /// Where this backend keeps objects (bucket or base directory).
+    fn location(&self) -> String;
+
+    /// Full key or path the backend actually uses for `file_path`.
+    fn object_key(&self, file_path: &str) -> String;
+
+    /// Time-limited URL clients can fetch the object from directly.
+    /// Returns `None` when the backend can't presign, so callers fall back to proxying.
+    async fn presigned_url(&self, _file_path: &str, _expiry: Duration) -> Result<Option<String>, StorageError> {
+        Ok(None)
+    }
+
+    /// Free space in bytes available for new objects.
+    /// Returns `None` when the backend has no meaningful capacity limit.
+    async fn available_space(&self) -> Result<Option<u64>, StorageError> {
+        Ok(None)
+    }
+
+    /// Total bytes stored as reported by the backend itself.
+    /// Returns `None` when the backend can't report usage.
+    async fn usage(&self) -> Result<Option<u64>, StorageError> {
+        Ok(None)
+    }
 }
 
 // Enum to represent storage backends
@@ -48,6 +128,8 @@ pub trait Storage: Send + Sync {
 pub enum StorageBackend {
     Local(LocalStorage),  // Local filesystem storage
     S3(S3Storage),        // AWS S3 or MinIO storage
+    Azure(AzureStorage),  // Azure Blob Storage
+    Memory(MemoryStorage), // Non-persistent in-process storage
 }
 
 // Implement Storage trait for StorageBackend enum
@@ -58,6 +140,17 @@ impl Storage for StorageBackend {
         match self {
             StorageBackend::Local(s) => s.upload(file_path, content).await,
             StorageBackend::S3(s) => s.upload(file_path, content).await,
+            StorageBackend::Azure(s) => s.upload(file_path, content).await,
+            StorageBackend::Memory(s) => s.upload(file_path, content).await,
+        }
+    }
+
+    async fn upload_stream(&self, file_path: &str, stream: UploadStream<'_>) -> Result<u64, StorageError> {
+        match self {
+            StorageBackend::Local(s) => s.upload_stream(file_path, stream).await,
+            StorageBackend::S3(s) => s.upload_stream(file_path, stream).await,
+            StorageBackend::Azure(s) => s.upload_stream(file_path, stream).await,
+            StorageBackend::Memory(s) => s.upload_stream(file_path, stream).await,
         }
     }
 
@@ -65,6 +158,8 @@ impl Storage for StorageBackend {
         match self {
             StorageBackend::Local(s) => s.download(file_path).await,
             StorageBackend::S3(s) => s.download(file_path).await,
+            StorageBackend::Azure(s) => s.download(file_path).await,
+            StorageBackend::Memory(s) => s.download(file_path).await,
         }
     }
 
@@ -72,17 +167,232 @@ impl Storage for StorageBackend {
         match self {
             StorageBackend::Local(s) => s.delete(file_path).await,
             StorageBackend::S3(s) => s.delete(file_path).await,
+            StorageBackend::Azure(s) => s.delete(file_path).await,
+            StorageBackend::Memory(s) => s.delete(file_path).await,
+        }
+    }
+
+    async fn exists(&self, file_path: &str) -> Result<bool, StorageError> {
+        match self {
+            StorageBackend::Local(s) => s.exists(file_path).await,
+            StorageBackend::S3(s) => s.exists(file_path).await,
+            StorageBackend::Azure(s) => s.exists(file_path).await,
+            StorageBackend::Memory(s) => s.exists(file_path).await,
+        }
+    }
+
+    async fn usage(&self) -> Result<Option<u64>, StorageError> {
+        match self {
+            StorageBackend::Local(s) => s.usage().await,
+            StorageBackend::S3(s) => s.usage().await,
+            StorageBackend::Azure(s) => s.usage().await,
+            StorageBackend::Memory(s) => s.usage().await,
+        }
+    }
+
+    async fn presigned_url(&self, file_path: &str, expiry: Duration) -> Result<Option<String>, StorageError> {
+        match self {
+            StorageBackend::Local(s) => s.presigned_url(file_path, expiry).await,
+            StorageBackend::S3(s) => s.presigned_url(file_path, expiry).await,
+            StorageBackend::Azure(s) => s.presigned_url(file_path, expiry).await,
+            StorageBackend::Memory(s) => s.presigned_url(file_path, expiry).await,
+        }
+    }
+
+    async fn available_space(&self) -> Result<Option<u64>, StorageError> {
+        match self {
+            StorageBackend::Local(s) => s.available_space().await,
+            StorageBackend::S3(s) => s.available_space().await,
+            StorageBackend::Azure(s) => s.available_space().await,
+            StorageBackend::Memory(s) => s.available_space().await,
+        }
+    }
+
+    async fn object_size(&self, file_path: &str) -> Result<Option<u64>, StorageError> {
+        match self {
+            StorageBackend::Local(s) => s.object_size(file_path).await,
+            StorageBackend::S3(s) => s.object_size(file_path).await,
+            StorageBackend::Azure(s) => s.object_size(file_path).await,
+            StorageBackend::Memory(s) => s.object_size(file_path).await,
+        }
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        match self {
+            StorageBackend::Local(s) => s.rename(from, to).await,
+            StorageBackend::S3(s) => s.rename(from, to).await,
+            StorageBackend::Azure(s) => s.rename(from, to).await,
+            StorageBackend::Memory(s) => s.rename(from, to).await,
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, StorageError> {
+        match self {
+            StorageBackend::Local(s) => s.list(prefix).await,
+            StorageBackend::S3(s) => s.list(prefix).await,
+            StorageBackend::Azure(s) => s.list(prefix).await,
+            StorageBackend::Memory(s) => s.list(prefix).await,
+        }
+    }
+
+    fn stored_path(&self, file_path: &str) -> String {
+        match self {
+            StorageBackend::Local(s) => s.stored_path(file_path),
+            StorageBackend::S3(s) => s.stored_path(file_path),
+            StorageBackend::Azure(s) => s.stored_path(file_path),
+            StorageBackend::Memory(s) => s.stored_path(file_path),
+        }
+    }
+
+    fn location(&self) -> String {
+        match self {
+            StorageBackend::Local(s) => s.location(),
+            StorageBackend::S3(s) => s.location(),
+            StorageBackend::Azure(s) => s.location(),
+            StorageBackend::Memory(s) => s.location(),
+        }
+    }
+
+    fn object_key(&self, file_path: &str) -> String {
+        match self {
+            StorageBackend::Local(s) => s.object_key(file_path),
+            StorageBackend::S3(s) => s.object_key(file_path),
+            StorageBackend::Azure(s) => s.object_key(file_path),
+            StorageBackend::Memory(s) => s.object_key(file_path),
         }
     }
 }
 
 // Initialize the storage backend based on config
 pub async fn init_storage(config: &Config) -> StorageBackend {
-    if config.use_s3 {
-        info!("Initializing S3 storage");
-        StorageBackend::S3(S3Storage::new(config).await)
-    } else {
-        info!("Initializing Local storage");
-        StorageBackend::Local(LocalStorage::new("uploads").await)
-    }
-}
\ No newline at end of file
+    match config.storage_backend {
+        StorageKind::S3 => {
+            info!("Initializing S3 storage");
+            StorageBackend::S3(S3Storage::new(config).await)
+        }
+        StorageKind::Azure => {
+            info!("Initializing Azure Blob storage");
+            StorageBackend::Azure(AzureStorage::new(config).await)
+        }
+        StorageKind::Local => {
+            info!("Initializing Local storage");
+            StorageBackend::Local(LocalStorage::new("uploads", &config.storage_prefix).await)
+        }
+        StorageKind::Memory => {
+            info!("Initializing in-memory storage; files will not survive a restart");
+            StorageBackend::Memory(MemoryStorage::new())
+        }
+    }
+}
+
+/// Confirm the backend is usable by writing and deleting a small probe object.
+pub async fn probe(storage: &StorageBackend) -> Result<(), StorageError> {
+    let key = ".probes/readiness";
+    storage.upload(key, Bytes::from_static(b"ok")).await?;
+    storage.delete(key).await
+}
+
+/// Probe `storage` every `retry` until it accepts a test write, then mark the
+/// service `ready`.
+pub async fn wait_until_ready(storage: &StorageBackend, ready: &AtomicBool, retry: Duration) {
+    loop {
+        match probe(storage).await {
+            Ok(()) => {
+                ready.store(true, Ordering::Relaxed);
+                info!("Storage backend ready");
+                return;
+            }
+            Err(e) => {
+                error!("Storage readiness probe failed: {}", e);
+                tokio::time::sleep(retry).await;
+            }
+        }
+    }
+}
+
+/// Clean up staged uploads older than `max_age`, left behind by uploads that
+/// crashed between staging and commit. A staged object whose file row was
+/// already written is moved to its live key; any other is deleted.
+/// Returns how many were removed.
+pub async fn clean_staging(storage: &StorageBackend, pool: &PgPool, max_age: Duration) -> Result<usize, StorageError> {
+    let now = SystemTime::now();
+    let mut removed = 0;
+
+    for object in storage.list(STAGING_PREFIX).await? {
+        let age = now.duration_since(object.last_modified).unwrap_or_default();
+        if age < max_age {
+            continue;
+        }
+
+        // Staged uploads are keyed by file id, with their gzip variant at
+        // `{id}.gz`; streamed uploads never have a row
+        let name = object.key.strip_prefix(STAGING_PREFIX).unwrap_or(&object.key);
+        let (id, suffix) = match name.strip_suffix(".gz") {
+            Some(id) => (id, ".gz"),
+            None => (name, ""),
+        };
+        let file_id = Uuid::parse_str(id).ok();
+        let committed = match file_id {
+            Some(id) => {
+                let row = sqlx::query!("SELECT storage_type, file_path FROM files WHERE id = $1", id)
+                    .fetch_optional(pool)
+                    .await;
+                match row {
+                    Ok(row) => row,
+                    Err(e) => {
+                        // Keep the object rather than risk deleting a file's only copy
+                        warn!("Failed to look up staged upload {}: {}", object.key, e);
+                        continue;
+                    }
+                }
+            }
+            None => None,
+        };
+
+        match committed {
+            Some(row) => {
+                let live_key = format!("{}{}", storage_relative_path(&row.storage_type, &row.file_path), suffix);
+                storage.rename(&object.key, &live_key).await?;
+                info!("Committed staged upload {} to {}", object.key, live_key);
+            }
+            None => {
+                storage.delete(&object.key).await?;
+                removed += 1;
+            }
+        }
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn wait_until_ready_retries_until_storage_accepts_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = StorageBackend::Local(LocalStorage::new(dir.path().to_str().unwrap(), "").await);
+
+        // A file where the probe's directory belongs makes every probe write fail
+        let blocker = dir.path().join(".probes");
+        std::fs::write(&blocker, b"").unwrap();
+
+        let ready = Arc::new(AtomicBool::new(false));
+        let waiter = tokio::spawn({
+            let (storage, ready) = (storage.clone(), ready.clone());
+            async move { wait_until_ready(&storage, &ready, Duration::from_millis(10)).await }
+        });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!ready.load(Ordering::Relaxed));
+        assert!(!waiter.is_finished());
+
+        std::fs::remove_file(&blocker).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), waiter).await.unwrap().unwrap();
+        assert!(ready.load(Ordering::Relaxed));
+        assert!(!storage.exists(".probes/readiness").await.unwrap());
+    }
+}
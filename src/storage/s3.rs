@@ -1,17 +1,27 @@
-use aws_config::meta::region::RegionProviderChain;
+use std::{sync::Arc, time::{Duration, SystemTime}};
+
+use aws_config::{meta::region::RegionProviderChain, timeout::TimeoutConfig};
 use aws_credential_types::Credentials;
 use aws_types::region::Region;
-use aws_sdk_s3::{Client, primitives::ByteStream};
-use bytes::Bytes;
+use aws_sdk_s3::{Client, presigning::PresigningConfig, primitives::ByteStream, types::{CompletedMultipartUpload, CompletedPart}};
+use bytes::{Bytes, BytesMut};
+use futures::TryStreamExt;
 use tracing::info;
 use async_trait::async_trait;
-use crate::{config::Config, storage::{Storage, StorageError}};
+use tokio::sync::{Semaphore, SemaphorePermit};
+use crate::{config::Config, storage::{Storage, StorageError, StoredObject, UploadStream}};
+
+/// Size of each part in a streamed multipart upload; S3 requires at least 5 MiB
+/// for every part but the last.
+const MULTIPART_PART_SIZE: usize = 8 * 1024 * 1024;
 
 // AWS S3 Storage backend
 #[derive(Clone)]
 pub struct S3Storage{
     client: Client,  // AWS S3 client
     bucket: String,  // S3 bucket name
+    prefix: String,  // Key prefix for every object this service writes
+    permits: Arc<Semaphore>, // Caps concurrent requests (and so open connections) to S3
 }
 
 impl S3Storage {
@@ -21,7 +31,16 @@ impl S3Storage {
             .or_default_provider()
             .or_else(Region::new("us-east-1"));
 
-        let mut aws_config_builder = aws_config::from_env().region(region_provider);
+        // Connection, read and overall operation timeouts for every S3 call
+        let timeout_config = TimeoutConfig::builder()
+            .connect_timeout(Duration::from_millis(config.s3_connect_timeout_ms))
+            .read_timeout(Duration::from_millis(config.s3_read_timeout_ms))
+            .operation_timeout(Duration::from_secs(config.s3_operation_timeout_secs))
+            .build();
+
+        let mut aws_config_builder = aws_config::from_env()
+            .region(region_provider)
+            .timeout_config(timeout_config);
 
         // Custom endpoint (e.g., for MinIO)
         if let Some(endpoint) = &config.s3_endpoint {
@@ -52,6 +71,69 @@ impl S3Storage {
         Self {
             client,
             bucket: config.s3_bucket.clone(),
+            prefix: config.storage_prefix.clone(),
+            permits: Arc::new(Semaphore::new(config.s3_max_concurrent_requests)),
+        }
+    }
+
+    /// Full object key for a path, including the configured prefix
+    fn key(&self, file_path: &str) -> String {
+        format!("{}{}", self.prefix, file_path)
+    }
+
+    /// Wait for a free request slot before talking to S3
+    async fn permit(&self) -> SemaphorePermit<'_> {
+        self.permits
+            .acquire()
+            .await
+            .expect("S3 request semaphore is never closed")
+    }
+
+    /// Upload `stream` as the parts of an already created multipart upload,
+    /// buffering at most one part in memory. Returns the parts and total size.
+    async fn upload_parts(
+        &self,
+        key: &str,
+        upload_id: &str,
+        mut stream: UploadStream<'_>,
+    ) -> Result<(Vec<CompletedPart>, u64), StorageError> {
+        let mut parts = Vec::new();
+        let mut buffer = BytesMut::new();
+        let mut total = 0u64;
+
+        loop {
+            let chunk = stream.try_next().await?;
+            let finished = chunk.is_none();
+            if let Some(chunk) = chunk {
+                total += chunk.len() as u64;
+                buffer.extend_from_slice(&chunk);
+            }
+
+            // Send full parts as they fill up, then whatever remains (at least one part)
+            if buffer.len() >= MULTIPART_PART_SIZE || (finished && (!buffer.is_empty() || parts.is_empty())) {
+                let part_number = parts.len() as i32 + 1;
+                let output = self.client
+                    .upload_part()
+                    .bucket(&self.bucket)
+                    .key(key)
+                    .upload_id(upload_id)
+                    .part_number(part_number)
+                    .body(ByteStream::from(buffer.split().freeze()))
+                    .send()
+                    .await
+                    .map_err(|e| StorageError::UploadError(e.to_string()))?;
+
+                parts.push(
+                    CompletedPart::builder()
+                        .part_number(part_number)
+                        .set_e_tag(output.e_tag().map(str::to_string))
+                        .build(),
+                );
+            }
+
+            if finished {
+                return Ok((parts, total));
+            }
         }
     }
 
@@ -88,31 +170,96 @@ impl Storage for S3Storage {
     /// Uploads content to S3 bucket
     async fn upload(&self, file_path: &str, content: Bytes) -> Result<String, StorageError>{
         let body = ByteStream::from(content);
-        
+        let _permit = self.permit().await;
+
         self.client
             .put_object()
             .bucket(&self.bucket)
-            .key(file_path)
+            .key(self.key(file_path))
             .body(body)
             .send()
             .await
             .map_err(|e| StorageError::UploadError(e.to_string()))?;
 
-        Ok(format!("s3://{}", file_path))
+        Ok(self.stored_path(file_path))
+    }
+
+    /// Streams content to S3 bucket as a multipart upload
+    async fn upload_stream(&self, file_path: &str, stream: UploadStream<'_>) -> Result<u64, StorageError> {
+        let key = self.key(file_path);
+        let _permit = self.permit().await;
+
+        let created = self.client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|e| StorageError::UploadError(e.to_string()))?;
+        let upload_id = created
+            .upload_id()
+            .ok_or_else(|| StorageError::UploadError("S3 returned no multipart upload id".into()))?
+            .to_string();
+
+        let (parts, total) = match self.upload_parts(&key, &upload_id, stream).await {
+            Ok(uploaded) => uploaded,
+            Err(e) => {
+                // Abort so S3 doesn't keep (and bill for) the orphaned parts
+                let _ = self.client
+                    .abort_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(&key)
+                    .upload_id(&upload_id)
+                    .send()
+                    .await;
+                return Err(e);
+            }
+        };
+
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&key)
+            .upload_id(&upload_id)
+            .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build())
+            .send()
+            .await
+            .map_err(|e| StorageError::UploadError(e.to_string()))?;
+
+        info!("Streamed {} bytes to s3: {}", total, key);
+
+        Ok(total)
+    }
+
+    /// Presigns a GET request for the object, valid for `expiry`
+    async fn presigned_url(&self, file_path: &str, expiry: Duration) -> Result<Option<String>, StorageError> {
+        let presigning = PresigningConfig::expires_in(expiry)
+            .map_err(|e| StorageError::PresignError(e.to_string()))?;
+
+        let request = self.client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.key(file_path))
+            .presigned(presigning)
+            .await
+            .map_err(|e| StorageError::PresignError(e.to_string()))?;
+
+        Ok(Some(request.uri().to_string()))
     }
 
     /// Downloads content from S3 bucket
     async fn download(&self, file_path: &str) -> Result<Bytes, StorageError> {
-        tracing::info!("S3 GET key = {}", file_path);
+        tracing::info!("S3 GET key = {}", self.key(file_path));
+        let _permit = self.permit().await;
         let response = self.client
             .get_object()
             .bucket(&self.bucket)
-            .key(file_path)
+            .key(self.key(file_path))
             .send()
             .await
-            .map_err(|e| {
-                tracing::error!("Wrong key was provided...");
-                StorageError::NotFound(e.to_string())
+            .map_err(|e| match e.as_service_error() {
+                Some(se) if se.is_no_such_key() => StorageError::NotFound(file_path.to_string()),
+                _ => StorageError::IoError(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())),
             })?;
 
 
@@ -127,10 +274,11 @@ impl Storage for S3Storage {
 
     /// Deletes a file from S3 bucket
     async fn delete(&self, file_path: &str) -> Result<(), StorageError> {
+        let _permit = self.permit().await;
         self.client
             .delete_object()
             .bucket(&self.bucket)
-            .key(file_path)
+            .key(self.key(file_path))
             .send()
             .await
             .map_err(|e| StorageError::DeleteError(e.to_string()))?;
@@ -139,4 +287,136 @@ impl Storage for S3Storage {
         Ok(())
     }
 
+    /// Checks whether an object exists with a HEAD request
+    async fn exists(&self, file_path: &str) -> Result<bool, StorageError> {
+        let _permit = self.permit().await;
+        match self.client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(self.key(file_path))
+            .send()
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|se| se.is_not_found()) => Ok(false),
+            Err(e) => Err(StorageError::IoError(std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))),
+        }
+    }
+
+    /// Returns an object's size from a HEAD request
+    async fn object_size(&self, file_path: &str) -> Result<Option<u64>, StorageError> {
+        let _permit = self.permit().await;
+        match self.client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(self.key(file_path))
+            .send()
+            .await
+        {
+            Ok(head) => Ok(Some(head.content_length().unwrap_or(0).max(0) as u64)),
+            Err(e) if e.as_service_error().is_some_and(|se| se.is_not_found()) => Ok(None),
+            Err(e) => Err(StorageError::IoError(std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))),
+        }
+    }
+
+    /// Copies an object to the new key, then deletes the original
+    async fn rename(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let _permit = self.permit().await;
+        self.client
+            .copy_object()
+            .bucket(&self.bucket)
+            .copy_source(encode_copy_source(&format!("{}/{}", self.bucket, self.key(from))))
+            .key(self.key(to))
+            .send()
+            .await
+            .map_err(|e| StorageError::UploadError(e.to_string()))?;
+
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(self.key(from))
+            .send()
+            .await
+            .map_err(|e| StorageError::DeleteError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    /// Lists objects under the prefix, with keys relative to the configured storage prefix
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, StorageError> {
+        let _permit = self.permit().await;
+        let mut pages = self.client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(self.key(prefix))
+            .into_paginator()
+            .send();
+
+        let mut objects = Vec::new();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| StorageError::ListError(e.to_string()))?;
+            for object in page.contents() {
+                let Some(key) = object.key() else { continue };
+                objects.push(StoredObject {
+                    key: key.strip_prefix(self.prefix.as_str()).unwrap_or(key).to_string(),
+                    size: object.size().unwrap_or(0).max(0) as u64,
+                    last_modified: object
+                        .last_modified()
+                        .and_then(|t| SystemTime::try_from(*t).ok())
+                        .unwrap_or(SystemTime::UNIX_EPOCH),
+                });
+            }
+        }
+
+        Ok(objects)
+    }
+
+    fn stored_path(&self, file_path: &str) -> String {
+        format!("s3://{}", file_path)
+    }
+
+    fn location(&self) -> String {
+        format!("s3://{}", self.bucket)
+    }
+
+    fn object_key(&self, file_path: &str) -> String {
+        self.key(file_path)
+    }
+
+    /// Sums the size of every object under the configured prefix
+    async fn usage(&self) -> Result<Option<u64>, StorageError> {
+        let _permit = self.permit().await;
+        let mut pages = self.client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(&self.prefix)
+            .into_paginator()
+            .send();
+
+        let mut total: u64 = 0;
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| StorageError::ListError(e.to_string()))?;
+            total += page
+                .contents()
+                .iter()
+                .map(|object| object.size().unwrap_or(0).max(0) as u64)
+                .sum::<u64>();
+        }
+
+        Ok(Some(total))
+    }
+
+}
+
+/// Percent-encode a `bucket/key` copy source; `/` separators are kept as-is.
+fn encode_copy_source(source: &str) -> String {
+    source
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
 }
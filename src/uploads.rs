@@ -0,0 +1,558 @@
+use axum::{Json, body::Body, extract::{Path, State}, http::{HeaderMap, StatusCode, header}, response::{IntoResponse, Response}};
+use bytes::BytesMut;
+use futures::{StreamExt, TryStreamExt};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    error::AppError,
+    handlers::{NewUpload, UploadContent, capture_head, ensure_disk_space, store_file},
+    models::*,
+    state::AppState,
+    storage::{STAGING_PREFIX, Storage},
+    utils::{ContentDigests, UploadHasher, parse_content_range},
+};
+
+/// Key prefix under which chunks of open upload sessions are kept.
+pub const CHUNK_PREFIX: &str = "chunks/";
+
+/// How long a finalize attempt may hold a session before another can take over.
+const FINALIZE_LEASE_MINUTES: i32 = 15;
+
+/// Open a resumable upload session for a file of known size.
+/// Chunks are then sent with `PATCH /uploads/{id}`.
+pub async fn create_upload_session(
+    State(state): State<AppState>,
+    Json(payload): Json<CreateUploadSession>,
+) -> Result<Response, AppError> {
+
+    let filename = payload.filename.trim();
+    if filename.is_empty() {
+        return Err(AppError::BadRequest("filename must not be empty".into()));
+    }
+    if payload.size <= 0 {
+        return Err(AppError::BadRequest("size must be positive".into()));
+    }
+    if payload.size as u64 > state.config.max_file_size {
+        return Err(AppError::PayloadTooLarge(format!(
+            "File size {} exceeds maximum limit of {} bytes",
+            payload.size, state.config.max_file_size
+        )));
+    }
+
+    let mime_type = match payload.mime_type.as_deref().map(str::trim).filter(|m| !m.is_empty()) {
+        Some(mime_type) => mime_type
+            .parse::<mime::Mime>()
+            .map_err(|_| AppError::BadRequest(format!("Invalid mime_type: {}", mime_type)))?
+            .to_string(),
+        None => state.config.default_mime_type.clone(),
+    };
+
+    // The chunks and the assembled file both need room
+    ensure_disk_space(&state, payload.size as u64 * 2).await?;
+
+    let session = sqlx::query_as!(
+        UploadSession,
+        r#"
+        INSERT INTO upload_sessions (id, original_filename, mime_type, custom_filename, total_size)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING *
+        "#,
+        Uuid::new_v4(),
+        filename,
+        mime_type,
+        payload.custom_filename.filter(|name| !name.is_empty()),
+        payload.size
+    )
+    .fetch_one(&state.pool)
+    .await?;
+
+    info!("Upload session created: {} ({} bytes)", session.id, session.total_size);
+
+    let status = session_status(&state, &session);
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, status.url.clone())],
+        Json(status),
+    ).into_response())
+}
+
+/// Report how much of an upload has been received, so a client can resume.
+pub async fn get_upload_session(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<UploadSessionStatus>, AppError> {
+    let session = fetch_session(&state, id).await?;
+    Ok(Json(session_status(&state, &session)))
+}
+
+/// Append the chunk described by `Content-Range` to an upload session. Each
+/// chunk must start where the previous one ended. The chunk completing the
+/// file stores it like any other upload and gets that upload's response;
+/// if that fails or is lost, `POST /uploads/{id}/complete` retries it.
+pub async fn append_upload_chunk(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, AppError> {
+
+    let session = fetch_session(&state, id).await?;
+
+    let content_range = headers.get(header::CONTENT_RANGE).and_then(|v| v.to_str().ok());
+    let (start, end) = chunk_range(content_range, &session)?;
+
+    // Read the chunk, refusing more bytes than its range announced
+    let expected = end - start + 1;
+    let mut data = BytesMut::new();
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.try_next().await.map_err(|e| {
+        error!("Error reading upload chunk: {}", e);
+        AppError::FileProcessingError(format!("Failed to read the chunk: {}", e))
+    })? {
+        if (data.len() + chunk.len()) as u64 > expected {
+            return Err(AppError::BadRequest("Chunk is longer than its Content-Range".into()));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    if data.len() as u64 != expected {
+        return Err(AppError::BadRequest("Chunk is shorter than its Content-Range".into()));
+    }
+
+    // Every attempt writes its own key, so a request that loses the race
+    // below can't overwrite the chunk that was accepted
+    let chunk_key = format!("{}{}", chunk_prefix(id), Uuid::new_v4());
+    state.storage.upload(&chunk_key, data.freeze()).await.map_err(|e| {
+        error!("Error storing chunk {}: {}", chunk_key, e);
+        AppError::InternalServerError("Failed to store chunk".into())
+    })?;
+
+    // Only advance from the offset this chunk was checked against, so two
+    // clients sending the same range can't both succeed
+    let session = sqlx::query_as!(
+        UploadSession,
+        r#"
+        UPDATE upload_sessions
+        SET received = $2, chunk_keys = array_append(chunk_keys, $4)
+        WHERE id = $1 AND received = $3
+        RETURNING *
+        "#,
+        id,
+        (end + 1) as i64,
+        session.received,
+        chunk_key
+    )
+    .fetch_optional(&state.pool)
+    .await?;
+
+    let Some(session) = session else {
+        let _ = state.storage.delete(&chunk_key).await;
+        return Err(AppError::Conflict(
+            "Upload session changed concurrently; check its progress and retry".into(),
+        ));
+    };
+
+    if session.received < session.total_size {
+        return Ok(Json(session_status(&state, &session)).into_response());
+    }
+
+    finalize_upload(&state, session.id).await
+}
+
+/// Check a chunk's `Content-Range` against `session`, returning its inclusive
+/// byte range. The chunk must fit the file and start where the last one ended.
+fn chunk_range(content_range: Option<&str>, session: &UploadSession) -> Result<(u64, u64), AppError> {
+    let content_range = content_range
+        .ok_or_else(|| AppError::BadRequest("Missing Content-Range header".into()))?;
+    let (start, end, total) = parse_content_range(content_range)
+        .ok_or_else(|| AppError::BadRequest(format!("Invalid Content-Range: {}", content_range)))?;
+
+    let total_size = session.total_size as u64;
+    if total.is_some_and(|total| total != total_size) {
+        return Err(AppError::BadRequest(format!(
+            "Content-Range total does not match the session size of {} bytes",
+            total_size
+        )));
+    }
+    if end >= total_size {
+        return Err(AppError::BadRequest("Chunk extends past the end of the file".into()));
+    }
+    if start != session.received as u64 {
+        return Err(AppError::Conflict(format!(
+            "Expected a chunk starting at byte {}",
+            session.received
+        )));
+    }
+
+    Ok((start, end))
+}
+
+/// Store a fully received upload, responding like `POST /upload`. The `PATCH`
+/// completing the file does this itself; clients call this to retry when
+/// that response was lost or failed.
+pub async fn complete_upload(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    finalize_upload(&state, id).await
+}
+
+/// Claim a complete session and store it. The work runs in its own task so
+/// a client disconnecting mid-way can't leave the session half finalized.
+async fn finalize_upload(state: &AppState, id: Uuid) -> Result<Response, AppError> {
+    let session = sqlx::query_as!(
+        UploadSession,
+        r#"
+        UPDATE upload_sessions
+        SET finalizing_until = NOW() + make_interval(mins => $2)
+        WHERE id = $1
+          AND received = total_size
+          AND (finalizing_until IS NULL OR finalizing_until < NOW())
+        RETURNING *
+        "#,
+        id,
+        FINALIZE_LEASE_MINUTES
+    )
+    .fetch_optional(&state.pool)
+    .await?;
+
+    let Some(session) = session else {
+        let session = fetch_session(state, id).await?;
+        return Err(AppError::Conflict(if session.received < session.total_size {
+            format!("Upload is incomplete: {} of {} bytes received", session.received, session.total_size)
+        } else {
+            "Upload is already being finalized".to_string()
+        }));
+    };
+
+    let task_state = state.clone();
+    tokio::spawn(async move { store_session(&task_state, session).await })
+        .await
+        .map_err(|e| {
+            error!("Finalize task for upload {} failed: {}", id, e);
+            AppError::InternalServerError("Failed to finalize upload".into())
+        })
+}
+
+/// Assemble a claimed session's chunks and run them through the regular
+/// upload pipeline. The session is discarded once the file is stored or
+/// rejected for good; after a server-side failure it is released so
+/// finalizing can be retried without re-sending any chunks.
+async fn store_session(state: &AppState, session: UploadSession) -> Response {
+    let id = session.id;
+    let result = match assemble_chunks(state, &session).await {
+        Ok((content, digests)) => {
+            store_file(state, NewUpload {
+                content,
+                digests,
+                original_filename: session.original_filename,
+                mime_type: session.mime_type,
+                custom_filename: session.custom_filename,
+                ttl_seconds: None,
+            }).await
+        }
+        Err(e) => Err(e),
+    };
+    let response = result.unwrap_or_else(IntoResponse::into_response);
+
+    let status = response.status();
+    let settled = status.is_success() || (status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS);
+    let cleanup = if settled {
+        discard_session(state, id).await
+    } else {
+        sqlx::query!("UPDATE upload_sessions SET finalizing_until = NULL WHERE id = $1", id)
+            .execute(&state.pool)
+            .await
+            .map(|_| ())
+            .map_err(AppError::from)
+    };
+    if let Err(e) = cleanup {
+        error!("Failed to clean up upload session {}: {}", id, e);
+    }
+
+    response
+}
+
+/// Join a session's chunks in byte order, buffering small files and
+/// streaming large ones into staging like a direct upload would be.
+async fn assemble_chunks(state: &AppState, session: &UploadSession) -> Result<(UploadContent, ContentDigests), AppError> {
+    let chunks = session.chunk_keys.clone();
+    let total_size = session.total_size as u64;
+
+    if total_size <= state.config.stream_upload_threshold {
+        let mut data = BytesMut::with_capacity(total_size as usize);
+        for chunk_key in &chunks {
+            let content = state.storage.download(chunk_key).await.map_err(|e| {
+                error!("Failed to read chunk {}: {}", chunk_key, e);
+                AppError::InternalServerError("Failed to assemble upload".into())
+            })?;
+            data.extend_from_slice(&content);
+        }
+        if data.len() as u64 != total_size {
+            error!("Chunks of upload {} add up to {} bytes, not {}", session.id, data.len(), total_size);
+            return Err(AppError::InternalServerError("Upload is missing chunks".into()));
+        }
+        let digests = ContentDigests::of(&data, state.config.checksum_algorithm);
+        return Ok((UploadContent::Buffered(data.freeze()), digests));
+    }
+
+    let path = format!("{}{}", STAGING_PREFIX, Uuid::new_v4());
+    let storage = &state.storage;
+    let mut hasher = UploadHasher::new(state.config.checksum_algorithm);
+    let mut head = BytesMut::new();
+    let stream = futures::stream::iter(chunks)
+        .then(move |chunk_key| async move { storage.download(&chunk_key).await })
+        .map(|content| {
+            let content = content.map_err(std::io::Error::other)?;
+            hasher.update(&content);
+            capture_head(&mut head, &content);
+            Ok(content)
+        });
+
+    match state.storage.upload_stream(&path, Box::pin(stream)).await {
+        Ok(size) if size == total_size => {
+            Ok((UploadContent::Streamed { path, size, head: head.freeze() }, hasher.finalize()))
+        }
+        Ok(size) => {
+            error!("Chunks of upload {} add up to {} bytes, not {}", session.id, size, total_size);
+            let _ = state.storage.delete(&path).await;
+            Err(AppError::InternalServerError("Upload is missing chunks".into()))
+        }
+        Err(e) => {
+            error!("Failed to assemble upload {}: {}", session.id, e);
+            let _ = state.storage.delete(&path).await;
+            Err(AppError::InternalServerError("Failed to assemble upload".into()))
+        }
+    }
+}
+
+/// Delete upload sessions idle for longer than `Config::upload_session_timeout_minutes`,
+/// with their chunks. Run periodically from main; returns how many were removed.
+pub async fn purge_stale_upload_sessions(state: &AppState) -> Result<usize, AppError> {
+    let timeout_minutes = i32::try_from(state.config.upload_session_timeout_minutes).unwrap_or(i32::MAX);
+    let stale = sqlx::query_scalar!(
+        "SELECT id FROM upload_sessions WHERE updated_at <= NOW() - make_interval(mins => $1)",
+        timeout_minutes
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    let mut removed = 0;
+    for id in stale {
+        discard_session(state, id).await?;
+        info!("Stale upload session discarded: {}", id);
+        removed += 1;
+    }
+
+    Ok(removed)
+}
+
+/// Delete a session's chunks and its row.
+async fn discard_session(state: &AppState, id: Uuid) -> Result<(), AppError> {
+    // Chunk deletion failures leave orphans but shouldn't keep the session alive
+    match state.storage.list(&chunk_prefix(id)).await {
+        Ok(chunks) => {
+            for chunk in chunks {
+                let _ = state.storage.delete(&chunk.key).await;
+            }
+        }
+        Err(e) => error!("Failed to list chunks of upload {}: {}", id, e),
+    }
+
+    sqlx::query!("DELETE FROM upload_sessions WHERE id = $1", id)
+        .execute(&state.pool)
+        .await?;
+    Ok(())
+}
+
+async fn fetch_session(state: &AppState, id: Uuid) -> Result<UploadSession, AppError> {
+    sqlx::query_as!(
+        UploadSession,
+        "SELECT * FROM upload_sessions WHERE id = $1",
+        id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Upload session not found".to_string()))
+}
+
+fn session_status(state: &AppState, session: &UploadSession) -> UploadSessionStatus {
+    let timeout = chrono::Duration::minutes(state.config.upload_session_timeout_minutes as i64);
+    UploadSessionStatus {
+        id: session.id,
+        url: state.config.public_url(&format!("/uploads/{}", session.id)),
+        size: session.total_size,
+        received: session.received,
+        expires_at: session.updated_at + timeout,
+    }
+}
+
+fn chunk_prefix(id: Uuid) -> String {
+    format!("{}{}/", CHUNK_PREFIX, id)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Router, http::Request};
+    use bytes::Bytes;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn session(received: i64, total_size: i64) -> UploadSession {
+        UploadSession {
+            id: Uuid::nil(),
+            original_filename: "notes.txt".to_string(),
+            mime_type: "text/plain".to_string(),
+            custom_filename: None,
+            total_size,
+            received,
+            chunk_keys: Vec::new(),
+            finalizing_until: None,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn chunk_range_accepts_the_next_chunk() {
+        assert_eq!(chunk_range(Some("bytes 0-4/10"), &session(0, 10)).unwrap(), (0, 4));
+        assert_eq!(chunk_range(Some("bytes 5-9/*"), &session(5, 10)).unwrap(), (5, 9));
+        assert_eq!(chunk_range(Some(" bytes 5-5/10 "), &session(5, 10)).unwrap(), (5, 5));
+    }
+
+    #[test]
+    fn chunk_range_rejects_malformed_headers() {
+        assert!(matches!(chunk_range(None, &session(0, 10)), Err(AppError::BadRequest(_))));
+        for header in ["", "bytes 0-4", "items 0-4/10", "bytes=0-4/10", "bytes 4-0/10", "bytes -4/10", "bytes 0-4/ten"] {
+            assert!(matches!(chunk_range(Some(header), &session(0, 10)), Err(AppError::BadRequest(_))), "{}", header);
+        }
+    }
+
+    #[test]
+    fn chunk_range_rejects_chunks_outside_the_file() {
+        assert!(matches!(chunk_range(Some("bytes 0-4/12"), &session(0, 10)), Err(AppError::BadRequest(_))));
+        assert!(matches!(chunk_range(Some("bytes 5-10/*"), &session(5, 10)), Err(AppError::BadRequest(_))));
+    }
+
+    #[test]
+    fn chunk_range_conflicts_on_duplicate_and_out_of_order_chunks() {
+        assert!(matches!(chunk_range(Some("bytes 0-4/10"), &session(5, 10)), Err(AppError::Conflict(_))));
+        assert!(matches!(chunk_range(Some("bytes 7-9/10"), &session(5, 10)), Err(AppError::Conflict(_))));
+    }
+
+    async fn app_with_pool(pool: sqlx::PgPool) -> (AppState, Router) {
+        let mut state = AppState::for_tests(&[]).await;
+        state.pool = pool;
+        (state.clone(), crate::build_router(state))
+    }
+
+    async fn send(app: &Router, request: Request<Body>) -> (StatusCode, serde_json::Value) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn chunk(id: &str, range: &str, data: &'static str) -> Request<Body> {
+        Request::patch(format!("/uploads/{}", id))
+            .header(header::CONTENT_RANGE, range)
+            .body(Body::from(data))
+            .unwrap()
+    }
+
+    fn complete(id: Uuid) -> Request<Body> {
+        Request::post(format!("/uploads/{}/complete", id)).body(Body::empty()).unwrap()
+    }
+
+    /// A session that has received all of `data` but was never finalized,
+    /// as if the response to its last `PATCH` had been lost.
+    async fn received_session(state: &AppState, data: &'static [u8]) -> Uuid {
+        let id = Uuid::new_v4();
+        let chunk_key = format!("{}{}", chunk_prefix(id), Uuid::new_v4());
+        state.storage.upload(&chunk_key, Bytes::from_static(data)).await.unwrap();
+        sqlx::query!(
+            r#"
+            INSERT INTO upload_sessions (id, original_filename, mime_type, total_size, received, chunk_keys)
+            VALUES ($1, 'notes.txt', 'text/plain', $2, $2, $3)
+            "#,
+            id,
+            data.len() as i64,
+            &[chunk_key][..]
+        )
+        .execute(&state.pool)
+        .await
+        .unwrap();
+        id
+    }
+
+    #[sqlx::test]
+    async fn chunks_must_arrive_in_order(pool: sqlx::PgPool) {
+        let (_, app) = app_with_pool(pool).await;
+        let create = Request::post("/uploads")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"filename": "notes.txt", "size": 10, "mime_type": "text/plain"}"#))
+            .unwrap();
+        let (status, session) = send(&app, create).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let id = session["id"].as_str().unwrap().to_string();
+
+        let (status, progress) = send(&app, chunk(&id, "bytes 0-4/10", "hello")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(progress["received"], 5);
+
+        // A resent chunk and one skipping ahead both conflict without moving the offset
+        let (status, _) = send(&app, chunk(&id, "bytes 0-4/10", "hello")).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = send(&app, chunk(&id, "bytes 7-9/10", "rld")).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (status, file) = send(&app, chunk(&id, "bytes 5-9/10", " worl")).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(file["size"], 10);
+
+        let (status, _) = send(&app, Request::get(format!("/uploads/{}", id)).body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn finalize_waits_for_a_held_lease_to_expire(pool: sqlx::PgPool) {
+        let (state, app) = app_with_pool(pool).await;
+        let id = received_session(&state, b"hello world").await;
+
+        sqlx::query!("UPDATE upload_sessions SET finalizing_until = NOW() + INTERVAL '1 minute' WHERE id = $1", id)
+            .execute(&state.pool)
+            .await
+            .unwrap();
+        let (status, body) = send(&app, complete(id)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["error"], "Upload is already being finalized");
+
+        // A lease left behind by a finalizer that died can be taken over
+        sqlx::query!("UPDATE upload_sessions SET finalizing_until = NOW() - INTERVAL '1 minute' WHERE id = $1", id)
+            .execute(&state.pool)
+            .await
+            .unwrap();
+        let (status, _) = send(&app, complete(id)).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    #[sqlx::test]
+    async fn concurrent_finalizes_store_the_file_once(pool: sqlx::PgPool) {
+        let (state, app) = app_with_pool(pool).await;
+        let id = received_session(&state, b"hello world").await;
+
+        let (first, second) = tokio::join!(send(&app, complete(id)), send(&app, complete(id)));
+        let mut statuses = [first.0, second.0];
+        statuses.sort();
+        assert_eq!(statuses[0], StatusCode::CREATED);
+        // The loser finds the lease held, or the session already discarded
+        assert!(matches!(statuses[1], StatusCode::CONFLICT | StatusCode::NOT_FOUND), "{:?}", statuses);
+
+        let files = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM files"#)
+            .fetch_one(&state.pool)
+            .await
+            .unwrap();
+        assert_eq!(files, 1);
+    }
+}
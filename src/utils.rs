@@ -1,5 +1,45 @@
-use std::path::Path;
+use std::{io::Cursor, path::Path};
+use base64::Engine;
+use image::ImageDecoder;
+use chrono::{DateTime, Utc};
+use encoding_rs::Encoding;
+use lofty::{file::{AudioFile, TaggedFileExt}, probe::Probe, tag::Accessor};
+use md5::Md5;
+use once_cell::sync::Lazy;
 use sha2::{Digest, Sha256};
+use syntect::{highlighting::{Theme, ThemeSet}, html::highlighted_html_for_string, parsing::{SyntaxReference, SyntaxSet}};
+use uuid::Uuid;
+
+use crate::{
+    config::{ChecksumAlgorithm, NamingStrategy, WatermarkPosition},
+    models::{AudioMetadata, Category, File},
+};
+
+/// MIME types (besides `text/*`) treated as documents.
+pub const DOCUMENT_MIME_TYPES: &[&str] = &[
+    "application/pdf",
+    "application/msword",
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    "application/vnd.ms-excel",
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    "application/vnd.ms-powerpoint",
+    "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    "application/rtf",
+    "application/vnd.oasis.opendocument.text",
+];
+
+/// MIME types treated as archives.
+pub const ARCHIVE_MIME_TYPES: &[&str] = &[
+    "application/zip",
+    "application/x-zip-compressed",
+    "application/x-tar",
+    "application/gzip",
+    "application/x-gzip",
+    "application/x-7z-compressed",
+    "application/x-rar-compressed",
+    "application/vnd.rar",
+    "application/x-bzip2",
+];
 
 /// Extracts the file extension from a filename and converts it to lowercase.
 pub fn get_file_extension(filename: &str) -> Option<String> {
@@ -9,6 +49,92 @@ pub fn get_file_extension(filename: &str) -> Option<String> {
         .map(|ext| ext.to_lowercase())
 }
 
+/// Builds the stored filename for an upload according to `strategy`.
+/// `name` is the custom or original filename; path separators are replaced so
+/// it can't escape the storage directory. `attempt` > 0 adds a ` (n)` suffix
+/// for `OriginalWithCollisionSuffix` when earlier candidates were taken.
+pub fn build_stored_filename(
+    strategy: NamingStrategy,
+    id: Uuid,
+    name: &str,
+    extension: &str,
+    now: DateTime<Utc>,
+    attempt: u32,
+) -> String {
+    let name = name.replace(['/', '\\'], "_");
+
+    match strategy {
+        NamingStrategy::Uuid => format!("{}.{}", id, extension),
+        NamingStrategy::UuidPrefixedOriginal => format!("{}_{}", id, name),
+        NamingStrategy::OriginalWithCollisionSuffix if attempt == 0 => name,
+        NamingStrategy::OriginalWithCollisionSuffix => match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => format!("{} ({}).{}", stem, attempt, ext),
+            _ => format!("{} ({})", name, attempt),
+        },
+        NamingStrategy::TimestampOriginal => {
+            format!("{}_{}", now.format("%Y%m%dT%H%M%S%3fZ"), name)
+        }
+    }
+}
+
+/// Converts a stored `file_path`/`thumbnail_path` into the key the storage backend expects,
+/// stripping the `s3://`, `azure://`, `memory://` or `uploads/` prefix recorded at upload time.
+pub fn storage_relative_path(storage_type: &str, stored_path: &str) -> String {
+    let prefix = match storage_type {
+        "s3" => "s3://",
+        "azure" => "azure://",
+        "memory" => "memory://",
+        _ => "uploads/",
+    };
+    stored_path
+        .strip_prefix(prefix)
+        .unwrap_or(stored_path)
+        .to_string()
+}
+
+/// `Content-Transfer-Encoding` of a multipart part (RFC 2045).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferEncoding {
+    /// `7bit`, `8bit` or `binary`: the bytes are the content.
+    Identity,
+    Base64,
+    QuotedPrintable,
+}
+
+impl TransferEncoding {
+    /// Parses a `Content-Transfer-Encoding` header value; `None` if unsupported.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "7bit" | "8bit" | "binary" => Some(TransferEncoding::Identity),
+            "base64" => Some(TransferEncoding::Base64),
+            "quoted-printable" => Some(TransferEncoding::QuotedPrintable),
+            _ => None,
+        }
+    }
+
+    /// Decodes a part's body into the original bytes.
+    pub fn decode(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            TransferEncoding::Identity => Ok(data.to_vec()),
+            TransferEncoding::Base64 => {
+                // MIME base64 is wrapped into lines, which the decoder doesn't accept
+                let encoded: Vec<u8> = data
+                    .iter()
+                    .copied()
+                    .filter(|b| !b.is_ascii_whitespace())
+                    .collect();
+                base64::engine::general_purpose::STANDARD
+                    .decode(encoded)
+                    .map_err(|e| format!("Invalid base64: {}", e))
+            }
+            TransferEncoding::QuotedPrintable => {
+                quoted_printable::decode(data, quoted_printable::ParseMode::Robust)
+                    .map_err(|e| format!("Invalid quoted-printable: {}", e))
+            }
+        }
+    }
+}
+
 /// Calculates SHA-256 checksum of the given data slice.
 pub fn calculate_sha256(data: &[u8]) -> String {
     let mut hasher = Sha256::new();
@@ -17,18 +143,346 @@ pub fn calculate_sha256(data: &[u8]) -> String {
     // {:x} means format the value as lowercase hexadecimal string
 }
 
+/// Calculates an upload checksum with the given algorithm.
+pub fn calculate_checksum(data: &[u8], algorithm: ChecksumAlgorithm) -> String {
+    let mut hasher = ChecksumHasher::new(algorithm);
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// Incremental upload checksum, fed chunk by chunk as bytes arrive.
+/// Produces the same string as `calculate_checksum` over the whole input.
+pub enum ChecksumHasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl ChecksumHasher {
+    pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Sha256 => ChecksumHasher::Sha256(Sha256::new()),
+            ChecksumAlgorithm::Blake3 => ChecksumHasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            ChecksumHasher::Sha256(hasher) => hasher.update(data),
+            ChecksumHasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    pub fn finalize(self) -> String {
+        match self {
+            ChecksumHasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+            ChecksumHasher::Blake3(hasher) => format!("blake3:{}", hasher.finalize().to_hex()),
+        }
+    }
+}
+
+/// Digests recorded for an upload: its checksum, made with `Config::checksum_algorithm`,
+/// and an MD5 for download managers that can only verify with that.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentDigests {
+    pub checksum: String,
+    pub md5: String,
+}
+
+impl ContentDigests {
+    pub fn of(data: &[u8], algorithm: ChecksumAlgorithm) -> Self {
+        let mut hasher = UploadHasher::new(algorithm);
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+/// Incremental `ContentDigests`, fed chunk by chunk as an upload arrives.
+pub struct UploadHasher {
+    checksum: ChecksumHasher,
+    md5: Md5,
+}
+
+impl UploadHasher {
+    pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+        UploadHasher { checksum: ChecksumHasher::new(algorithm), md5: Md5::new() }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.checksum.update(data);
+        self.md5.update(data);
+    }
+
+    pub fn finalize(self) -> ContentDigests {
+        ContentDigests {
+            checksum: self.checksum.finalize(),
+            md5: format!("{:x}", self.md5.finalize()),
+        }
+    }
+}
+
+/// Checksums an image's decoded RGBA pixels and dimensions, ignoring metadata
+/// and container details, so images that differ only in EXIF hash the same.
+/// The EXIF orientation is applied first, since it changes how the image is
+/// displayed: a rotated copy only matches an original that looks the same.
+pub async fn normalized_image_checksum(
+    data: &[u8],
+    algorithm: ChecksumAlgorithm,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let data = data.to_vec();
+
+    tokio::task::spawn_blocking(move || {
+        let mut decoder = image::ImageReader::new(Cursor::new(&data))
+            .with_guessed_format()?
+            .into_decoder()?;
+        let orientation = decoder.orientation()?;
+        let mut img = image::DynamicImage::from_decoder(decoder)?;
+        img.apply_orientation(orientation);
+        let pixels = img.to_rgba8();
+        let mut hasher = ChecksumHasher::new(algorithm);
+        hasher.update(&pixels.width().to_be_bytes());
+        hasher.update(&pixels.height().to_be_bytes());
+        hasher.update(pixels.as_raw());
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(hasher.finalize())
+    }).await?
+}
+
+/// Builds an ETag for a file's metadata from its id, last update time and checksum.
+pub fn metadata_etag(file: &File) -> String {
+    let fingerprint = format!(
+        "{}:{}:{}",
+        file.id,
+        file.updated_at.map(|t| t.timestamp_micros()).unwrap_or_default(),
+        file.checksum.as_deref().unwrap_or("")
+    );
+    format!("\"{}\"", &calculate_sha256(fingerprint.as_bytes())[..32])
+}
+
+/// Checks whether an `If-None-Match` header value matches the given ETag.
+/// Supports `*`, comma-separated lists and weak (`W/`) validators.
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if_none_match
+        .split(',')
+        .map(|candidate| candidate.trim())
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
 /// Checks if a MIME type represents an image.
 pub fn is_file_mime_type(mime_type: &str) -> bool {
     mime_type.starts_with("image/")
 }
 
-/// Generates a thumbnail image from the given file data asynchronously.
+/// Sniffs a file's real MIME type from its magic bytes.
+/// Returns `None` for formats without a signature, such as plain text.
+pub fn detect_mime(data: &[u8]) -> Option<String> {
+    infer::get(data).map(|kind| kind.mime_type().to_string())
+}
+
+/// Checks that a declared MIME type and file extension agree with the type
+/// sniffed from the content. `undeclared` is the MIME type used when the
+/// client sent none, which matches anything.
+pub fn matches_detected_type(data: &[u8], declared_mime: &str, undeclared: &str, extension: &str) -> bool {
+    let Some(kind) = infer::get(data) else {
+        return true;
+    };
+
+    let essence = declared_mime.split(';').next().unwrap_or("").trim().to_lowercase();
+    let mime_matches = essence == undeclared
+        || essence == "application/octet-stream"
+        || canonical_mime(&essence) == kind.mime_type();
+    let extension_matches = canonical_extension(extension) == canonical_extension(kind.extension());
+
+    mime_matches && extension_matches
+}
+
+/// Maps legacy MIME aliases some browsers still send onto the name `infer` reports.
+fn canonical_mime(mime: &str) -> &str {
+    match mime {
+        "image/jpg" | "image/pjpeg" => "image/jpeg",
+        "image/x-png" => "image/png",
+        "application/x-zip-compressed" | "application/x-zip" => "application/zip",
+        "application/x-pdf" => "application/pdf",
+        other => other,
+    }
+}
+
+/// Maps alternative spellings of an extension onto one name.
+fn canonical_extension(extension: &str) -> &str {
+    match extension {
+        "jpeg" | "jpe" => "jpg",
+        "tiff" => "tif",
+        other => other,
+    }
+}
+
+/// Checks if a MIME type is safe for browsers to render inline.
+/// SVG and HTML are excluded since they can carry scripts.
+pub fn is_inline_previewable(mime_type: &str) -> bool {
+    let essence = mime_type.split(';').next().unwrap_or("").trim().to_lowercase();
+    (essence.starts_with("image/") && essence != "image/svg+xml")
+        || essence == "application/pdf"
+        || essence == "text/plain"
+}
+
+/// Checks if a MIME type is text-like enough to shrink noticeably when gzipped.
+pub fn is_compressible(mime_type: &str) -> bool {
+    let essence = mime_type.split(';').next().unwrap_or("").trim().to_lowercase();
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json" | "application/javascript" | "application/xml" | "image/svg+xml"
+        )
+}
+
+/// Whether an `Accept-Encoding` header value accepts `coding`, honouring
+/// `;q=0` and falling back to a `*` entry.
+pub fn accepts_encoding(accept_encoding: &str, coding: &str) -> bool {
+    let mut wildcard = None;
+    for entry in accept_encoding.split(',') {
+        let mut parts = entry.split(';');
+        let name = parts.next().unwrap_or("").trim();
+        let quality = parts
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .and_then(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        if name.eq_ignore_ascii_case(coding) {
+            return quality > 0.0;
+        }
+        if name == "*" {
+            wildcard = Some(quality > 0.0);
+        }
+    }
+    wildcard.unwrap_or(false)
+}
+
+/// Gzips `data` at the best compression level asynchronously.
+pub async fn gzip(data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let data = data.to_vec();
+
+    tokio::task::spawn_blocking(move || {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        std::io::Write::write_all(&mut encoder, &data)?;
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(encoder.finish()?)
+    }).await?
+}
+
+/// Parses a `Content-Range: bytes <start>-<end>/<total>` request header into
+/// the inclusive byte range and the total size (`None` for `*`).
+pub fn parse_content_range(value: &str) -> Option<(u64, u64, Option<u64>)> {
+    let (range, total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.trim().split_once('-')?;
+    let start: u64 = start.trim().parse().ok()?;
+    let end: u64 = end.trim().parse().ok()?;
+    let total = match total.trim() {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+
+    (start <= end).then_some((start, end, total))
+}
+
+/// Maps a MIME type to a broad file category.
+pub fn mime_category(mime: &str) -> Category {
+    // Ignore parameters such as "; charset=utf-8"
+    let essence = mime.split(';').next().unwrap_or("").trim().to_lowercase();
+
+    if essence.starts_with("image/") {
+        Category::Image
+    } else if essence.starts_with("video/") {
+        Category::Video
+    } else if essence.starts_with("audio/") {
+        Category::Audio
+    } else if ARCHIVE_MIME_TYPES.contains(&essence.as_str()) {
+        Category::Archive
+    } else if essence.starts_with("text/") || DOCUMENT_MIME_TYPES.contains(&essence.as_str()) {
+        Category::Document
+    } else {
+        Category::Other
+    }
+}
+
+/// Transcodes text to `target`. The source encoding comes from the stored
+/// `charset` parameter when present, then a BOM, and is otherwise detected
+/// from the bytes themselves.
+pub fn transcode_text(data: &[u8], mime_type: &str, target: &'static Encoding) -> Vec<u8> {
+    let declared = mime_type
+        .parse::<mime::Mime>()
+        .ok()
+        .and_then(|m| m.get_param(mime::CHARSET).map(|c| c.to_string()))
+        .and_then(|label| Encoding::for_label(label.as_bytes()));
+
+    let source = declared
+        .or_else(|| Encoding::for_bom(data).map(|(encoding, _)| encoding))
+        .unwrap_or_else(|| {
+            let mut detector = chardetng::EncodingDetector::new();
+            detector.feed(data, true);
+            detector.guess(None, true)
+        });
+
+    let (text, _, _) = source.decode(data);
+    let (encoded, _, _) = target.encode(&text);
+    encoded.into_owned()
+}
+
+/// Totals read from a zip archive's central directory.
+pub struct ArchiveStats {
+    pub entries: usize,
+    pub uncompressed_size: u64,
+}
+
+/// Reads the central directory of a zip archive without extracting any entry.
+pub fn inspect_zip(data: &[u8]) -> Result<ArchiveStats, zip::result::ZipError> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data))?;
+    let mut uncompressed_size: u64 = 0;
+
+    for i in 0..archive.len() {
+        // by_index_raw only reads entry metadata, no decompression happens
+        let entry = archive.by_index_raw(i)?;
+        uncompressed_size = uncompressed_size.saturating_add(entry.size());
+    }
+
+    Ok(ArchiveStats { entries: archive.len(), uncompressed_size })
+}
+
+/// Extracts duration, bitrate and common tags from an audio file.
+/// Missing tags are returned as `None` rather than treated as errors.
+pub fn extract_audio_metadata(data: &[u8]) -> Result<AudioMetadata, lofty::error::LoftyError> {
+    let tagged_file = Probe::new(Cursor::new(data))
+        .guess_file_type()?
+        .read()?;
+
+    let properties = tagged_file.properties();
+    let mut metadata = AudioMetadata {
+        duration_ms: Some(properties.duration().as_millis() as i64),
+        bitrate_kbps: properties.audio_bitrate().map(|b| b as i32),
+        ..Default::default()
+    };
+
+    // Prefer the format's primary tag (e.g. ID3v2 for MP3), fall back to any tag present
+    if let Some(tag) = tagged_file.primary_tag().or_else(|| tagged_file.first_tag()) {
+        metadata.title = tag.title().map(|s| s.into_owned());
+        metadata.artist = tag.artist().map(|s| s.into_owned());
+        metadata.album = tag.album().map(|s| s.into_owned());
+    }
+
+    Ok(metadata)
+}
+
+/// Renders thumbnails of an image fitting each `(width, height)` bounding box,
+/// keeping its aspect ratio and decoding it only once.
+/// Returns the encoded thumbnails, in the same order as `sizes`.
 pub async fn generate_thumbnail(
     data: &[u8],
-    base_name: &str
-) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    format: ConvertFormat,
+    sizes: &[(u32, u32)],
+) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
     let data = data.to_vec();
-    let base = base_name.to_string();
+    let sizes = sizes.to_vec();
 
     tokio::task::spawn_blocking(move || { // spawn_blocking used when cpu heavy work so other task don't stop processing
         // Load image from memory bytes
@@ -36,21 +490,527 @@ pub async fn generate_thumbnail(
 
         // doing this directly without spawn_blocking in async code would block the executor.
 
-        // Resize image to a thumbnail (max width/height = 200px)
-        let thumnail= img.thumbnail(200, 200);
+        sizes
+            .into_iter()
+            .map(|(width, height)| {
+                // Resize image to fit within width x height, keeping its aspect ratio
+                let thumnail = img.thumbnail(width, height);
+
+                // Encode thumbnail in the configured format
+                Ok(encode_image(&thumnail, format, 85)?)
+            })
+            .collect::<Result<Vec<_>, Box<dyn std::error::Error + Send + Sync>>>()
+    }).await?
+}
+
+/// Output formats supported by the image conversion endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConvertFormat {
+    Jpeg,
+    Png,
+    Webp,
+}
+
+impl ConvertFormat {
+    /// Parses a user-supplied format name (`jpeg`/`jpg`, `png`, `webp`).
+    pub fn parse(format: &str) -> Option<Self> {
+        match format.to_lowercase().as_str() {
+            "jpeg" | "jpg" => Some(ConvertFormat::Jpeg),
+            "png" => Some(ConvertFormat::Png),
+            "webp" => Some(ConvertFormat::Webp),
+            _ => None,
+        }
+    }
+
+    /// Format of a stored image, judged by its file extension.
+    pub fn from_path(path: &str) -> Option<Self> {
+        Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Self::parse)
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ConvertFormat::Jpeg => "jpg",
+            ConvertFormat::Png => "png",
+            ConvertFormat::Webp => "webp",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ConvertFormat::Jpeg => "image/jpeg",
+            ConvertFormat::Png => "image/png",
+            ConvertFormat::Webp => "image/webp",
+        }
+    }
+}
 
-        // Get system temporary directory (OS-specific)
-        let temp_dir = std::env::temp_dir(); // it is path for temp files every os has one.
+impl std::str::FromStr for ConvertFormat {
+    type Err = String;
 
-        // Construct temporary output path for thumbnail
-        let output_path = temp_dir.join(format!("{}_thumb.jpg", base));
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s).ok_or_else(|| format!("Unknown image format: {}", s))
+    }
+}
 
-        // Save thumbnail as JPEG
-        thumnail.save_with_format(&output_path, image::ImageFormat::Jpeg)?;
+/// Decodes an image and re-encodes it in the requested format asynchronously.
+/// `quality` (1-100) only applies to JPEG output.
+pub async fn convert_image(
+    data: &[u8],
+    format: ConvertFormat,
+    quality: u8,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let data = data.to_vec();
 
-        // Convert PathBuf to String safely
-        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(
-            output_path.to_string_lossy().into_owned()
-        ) // to_string_lossy converts PathBuf to Cow<str>.
+    tokio::task::spawn_blocking(move || {
+        let img = image::load_from_memory(&data)?;
+        encode_image(&img, format, quality)
     }).await?
-}
\ No newline at end of file
+}
+
+/// Encodes an image in `format`; `quality` (1-100) only applies to JPEG.
+fn encode_image(
+    img: &image::DynamicImage,
+    format: ConvertFormat,
+    quality: u8,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut output = Cursor::new(Vec::new());
+
+    match format {
+        ConvertFormat::Jpeg => {
+            // JPEG has no alpha channel, so flatten to RGB first
+            let rgb = image::DynamicImage::ImageRgb8(img.to_rgb8());
+            let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut output, quality);
+            encoder.encode_image(&rgb)?;
+        }
+        ConvertFormat::Png => img.write_to(&mut output, image::ImageFormat::Png)?,
+        ConvertFormat::Webp => img.write_to(&mut output, image::ImageFormat::WebP)?,
+    }
+
+    Ok(output.into_inner())
+}
+
+/// Renders `text` as a PNG QR code at least `size` pixels square.
+pub fn generate_qr_png(text: &str, size: u32) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let code = qrcode::QrCode::new(text.as_bytes())?;
+    let image = code
+        .render::<image::Luma<u8>>()
+        .min_dimensions(size, size)
+        .build();
+
+    let mut output = Cursor::new(Vec::new());
+    image.write_to(&mut output, image::ImageFormat::Png)?;
+    Ok(output.into_inner())
+}
+
+/// Syntax definitions and theme used for text previews, loaded on first use.
+static SYNTAX_SET: Lazy<SyntaxSet> = Lazy::new(SyntaxSet::load_defaults_newlines);
+static PREVIEW_THEME: Lazy<Theme> = Lazy::new(|| {
+    ThemeSet::load_defaults()
+        .themes
+        .remove("InspiredGitHub")
+        .expect("syntect ships the InspiredGitHub theme")
+});
+
+/// Syntax to highlight a file with: matched by extension, falling back to
+/// plain text for `text/*`. `None` means the file can't be previewed.
+pub fn preview_syntax(mime_type: &str, extension: Option<&str>) -> Option<&'static SyntaxReference> {
+    extension
+        .and_then(|ext| SYNTAX_SET.find_syntax_by_extension(ext))
+        .or_else(|| mime_type.starts_with("text/").then(|| SYNTAX_SET.find_syntax_plain_text()))
+}
+
+/// Renders `text` as a standalone HTML page with inline syntax highlighting.
+pub async fn render_preview_html(
+    text: String,
+    syntax: &'static SyntaxReference,
+    title: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let title = escape_html(title);
+
+    tokio::task::spawn_blocking(move || {
+        let body = highlighted_html_for_string(&text, &SYNTAX_SET, syntax, &PREVIEW_THEME)?;
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{}</title></head>\n<body>\n{}</body>\n</html>\n",
+            title, body
+        ))
+    }).await?
+}
+
+/// Escapes text for use inside HTML element content or attribute values.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Overlays a watermark onto an image and returns it re-encoded in `format`.
+/// The watermark is scaled down to at most a third of the image width.
+pub async fn apply_watermark(
+    data: &[u8],
+    watermark: std::sync::Arc<image::RgbaImage>,
+    position: WatermarkPosition,
+    opacity: f32,
+    format: ConvertFormat,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let data = data.to_vec();
+
+    tokio::task::spawn_blocking(move || {
+        let mut base = image::load_from_memory(&data)?.to_rgba8();
+        let (width, height) = base.dimensions();
+
+        // Shrink the watermark so it never covers most of the image
+        let max_width = (width / 3).max(1);
+        let mut mark = if watermark.width() > max_width || watermark.height() > height {
+            image::DynamicImage::ImageRgba8((*watermark).clone())
+                .thumbnail(max_width, height)
+                .to_rgba8()
+        } else {
+            (*watermark).clone()
+        };
+
+        // Apply opacity by scaling the watermark's alpha channel
+        for pixel in mark.pixels_mut() {
+            pixel.0[3] = (pixel.0[3] as f32 * opacity).round() as u8;
+        }
+
+        let margin = 4i64;
+        let (w, h) = (width as i64, height as i64);
+        let (mw, mh) = (mark.width() as i64, mark.height() as i64);
+        let (x, y) = match position {
+            WatermarkPosition::TopLeft => (margin, margin),
+            WatermarkPosition::TopRight => (w - mw - margin, margin),
+            WatermarkPosition::BottomLeft => (margin, h - mh - margin),
+            WatermarkPosition::BottomRight => (w - mw - margin, h - mh - margin),
+            WatermarkPosition::Center => ((w - mw) / 2, (h - mh) / 2),
+        };
+        image::imageops::overlay(&mut base, &mark, x, y);
+
+        encode_image(&image::DynamicImage::ImageRgba8(base), format, 85)
+    }).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_stored_filename_applies_each_strategy() {
+        let id = Uuid::nil();
+        let now = DateTime::parse_from_rfc3339("2026-01-05T10:31:22.500Z").unwrap().with_timezone(&Utc);
+
+        assert_eq!(
+            build_stored_filename(NamingStrategy::Uuid, id, "days.png", "png", now, 0),
+            "00000000-0000-0000-0000-000000000000.png"
+        );
+        assert_eq!(
+            build_stored_filename(NamingStrategy::UuidPrefixedOriginal, id, "days.png", "png", now, 0),
+            "00000000-0000-0000-0000-000000000000_days.png"
+        );
+        assert_eq!(
+            build_stored_filename(NamingStrategy::TimestampOriginal, id, "days.png", "png", now, 0),
+            "20260105T103122500Z_days.png"
+        );
+    }
+
+    #[test]
+    fn build_stored_filename_suffixes_collisions_before_the_extension() {
+        let (id, now) = (Uuid::nil(), Utc::now());
+        let name = |name, attempt| {
+            build_stored_filename(NamingStrategy::OriginalWithCollisionSuffix, id, name, "gz", now, attempt)
+        };
+
+        assert_eq!(name("report.tar.gz", 0), "report.tar.gz");
+        assert_eq!(name("report.tar.gz", 2), "report.tar (2).gz");
+        assert_eq!(name("README", 1), "README (1)");
+        assert_eq!(name(".env", 1), ".env (1)");
+    }
+
+    #[test]
+    fn build_stored_filename_strips_path_separators() {
+        let name = build_stored_filename(NamingStrategy::OriginalWithCollisionSuffix, Uuid::nil(), "../etc\\passwd", "", Utc::now(), 0);
+        assert_eq!(name, ".._etc_passwd");
+    }
+
+    #[test]
+    fn checksum_hasher_matches_calculate_checksum() {
+        let data = b"the quick brown fox jumps over the lazy dog";
+        for algorithm in [ChecksumAlgorithm::Sha256, ChecksumAlgorithm::Blake3] {
+            let mut hasher = ChecksumHasher::new(algorithm);
+            for chunk in data.chunks(7) {
+                hasher.update(chunk);
+            }
+            assert_eq!(hasher.finalize(), calculate_checksum(data, algorithm));
+        }
+    }
+
+    #[test]
+    fn upload_hasher_matches_content_digests() {
+        let data = b"the quick brown fox jumps over the lazy dog";
+        let mut hasher = UploadHasher::new(ChecksumAlgorithm::Sha256);
+        for chunk in data.chunks(7) {
+            hasher.update(chunk);
+        }
+        let digests = hasher.finalize();
+        assert_eq!(digests, ContentDigests::of(data, ChecksumAlgorithm::Sha256));
+        assert_eq!(digests.checksum, calculate_checksum(data, ChecksumAlgorithm::Sha256));
+        assert_eq!(digests.md5, "77add1d5f41223d5582fca736a5cb335");
+    }
+
+    #[test]
+    fn checksums_record_their_algorithm() {
+        let sha256 = calculate_checksum(b"", ChecksumAlgorithm::Sha256);
+        let blake3 = calculate_checksum(b"", ChecksumAlgorithm::Blake3);
+
+        assert_eq!(sha256, "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(blake3, "blake3:af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262");
+        assert_eq!(ChecksumAlgorithm::of(&sha256), ChecksumAlgorithm::Sha256);
+        assert_eq!(ChecksumAlgorithm::of(&blake3), ChecksumAlgorithm::Blake3);
+    }
+
+    #[test]
+    fn transfer_encoding_parses_header_values() {
+        assert_eq!(TransferEncoding::parse(" BASE64 "), Some(TransferEncoding::Base64));
+        assert_eq!(TransferEncoding::parse("quoted-printable"), Some(TransferEncoding::QuotedPrintable));
+        assert_eq!(TransferEncoding::parse("8bit"), Some(TransferEncoding::Identity));
+        assert_eq!(TransferEncoding::parse("x-uuencode"), None);
+    }
+
+    #[test]
+    fn transfer_encoding_decodes_part_bodies() {
+        assert_eq!(TransferEncoding::Identity.decode(b"a=b\r\n").unwrap(), b"a=b\r\n");
+        assert_eq!(TransferEncoding::Base64.decode(b"aGVs\r\nbG8g\r\nd29y\r\nbGQ=\r\n").unwrap(), b"hello world");
+        assert_eq!(
+            TransferEncoding::QuotedPrintable.decode(b"caf=C3=A9 =\r\nbar").unwrap(),
+            "caf\u{e9} bar".as_bytes()
+        );
+        assert!(TransferEncoding::Base64.decode(b"not base64!").is_err());
+    }
+
+    #[test]
+    fn accepts_encoding_honours_quality_and_wildcards() {
+        assert!(accepts_encoding("gzip, deflate, br", "gzip"));
+        assert!(accepts_encoding("deflate, GZIP;q=0.5", "gzip"));
+        assert!(!accepts_encoding("gzip;q=0, *", "gzip"));
+        assert!(accepts_encoding("br, *", "gzip"));
+        assert!(!accepts_encoding("*;q=0", "gzip"));
+        assert!(!accepts_encoding("identity", "gzip"));
+        assert!(!accepts_encoding("", "gzip"));
+    }
+
+    #[test]
+    fn parse_content_range_reads_range_and_total() {
+        assert_eq!(parse_content_range("bytes 0-1023/4096"), Some((0, 1023, Some(4096))));
+        assert_eq!(parse_content_range(" bytes 1024-2047/* "), Some((1024, 2047, None)));
+        assert_eq!(parse_content_range("bytes 7-7/8"), Some((7, 7, Some(8))));
+    }
+
+    #[test]
+    fn parse_content_range_rejects_malformed_values() {
+        assert_eq!(parse_content_range("bytes 10-5/20"), None);
+        assert_eq!(parse_content_range("bytes 0-9"), None);
+        assert_eq!(parse_content_range("items 0-9/10"), None);
+        assert_eq!(parse_content_range("bytes -9/10"), None);
+        assert_eq!(parse_content_range("bytes 0-9/ten"), None);
+    }
+
+    #[test]
+    fn transcode_text_uses_the_declared_charset() {
+        let latin1 = b"caf\xe9";
+        let utf8 = transcode_text(latin1, "text/plain; charset=iso-8859-1", encoding_rs::UTF_8);
+        assert_eq!(utf8, "caf\u{e9}".as_bytes());
+    }
+
+    #[test]
+    fn transcode_text_falls_back_to_the_bom() {
+        let with_bom = b"\xef\xbb\xbfh\xc3\xa9";
+        let windows_1252 = transcode_text(with_bom, "text/plain", encoding_rs::WINDOWS_1252);
+        assert_eq!(windows_1252, b"h\xe9");
+    }
+
+    #[test]
+    fn inspect_zip_totals_the_central_directory() {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for name in ["a.txt", "b.txt"] {
+            writer.start_file(name, options).unwrap();
+            std::io::Write::write_all(&mut writer, &[b'x'; 1000]).unwrap();
+        }
+        let archive = writer.finish().unwrap().into_inner();
+
+        let stats = inspect_zip(&archive).unwrap();
+        assert_eq!(stats.entries, 2);
+        assert_eq!(stats.uncompressed_size, 2000);
+    }
+
+    #[test]
+    fn inspect_zip_rejects_non_archives() {
+        assert!(inspect_zip(b"definitely not a zip file").is_err());
+    }
+
+    #[test]
+    fn mime_category_groups_by_essence() {
+        let cases = [
+            ("image/png", Category::Image),
+            ("IMAGE/JPEG", Category::Image),
+            ("video/mp4", Category::Video),
+            ("audio/mpeg", Category::Audio),
+            ("application/zip", Category::Archive),
+            ("application/x-7z-compressed", Category::Archive),
+            ("text/plain; charset=utf-8", Category::Document),
+            ("application/pdf", Category::Document),
+            (" application/vnd.oasis.opendocument.text ", Category::Document),
+            ("application/octet-stream", Category::Other),
+            ("application/json", Category::Other),
+            ("", Category::Other),
+        ];
+        for (mime, category) in cases {
+            assert_eq!(mime_category(mime), category, "{}", mime);
+        }
+    }
+
+    const PNG_HEADER: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    #[test]
+    fn detect_mime_sniffs_magic_bytes() {
+        assert_eq!(detect_mime(PNG_HEADER).as_deref(), Some("image/png"));
+        assert_eq!(detect_mime(b"%PDF-1.7\n").as_deref(), Some("application/pdf"));
+        assert_eq!(detect_mime(b"just some notes"), None);
+    }
+
+    #[test]
+    fn matches_detected_type_rejects_a_renamed_png() {
+        assert!(!matches_detected_type(PNG_HEADER, "application/pdf", "application/octet-stream", "pdf"));
+        // Declaring the real type doesn't help while the extension still lies
+        assert!(!matches_detected_type(PNG_HEADER, "image/png", "application/octet-stream", "pdf"));
+        assert!(!matches_detected_type(PNG_HEADER, "application/pdf", "application/octet-stream", "png"));
+    }
+
+    #[test]
+    fn matches_detected_type_accepts_genuine_files() {
+        assert!(matches_detected_type(PNG_HEADER, "image/png", "application/octet-stream", "png"));
+        assert!(matches_detected_type(PNG_HEADER, "image/x-png; name=a.png", "application/octet-stream", "png"));
+        assert!(matches_detected_type(b"\xFF\xD8\xFF\xE0\0\x10JFIF\0", "image/jpg", "application/octet-stream", "jpeg"));
+        // No declared type matches whatever was sniffed
+        assert!(matches_detected_type(PNG_HEADER, "application/octet-stream", "application/octet-stream", "png"));
+    }
+
+    #[test]
+    fn matches_detected_type_allows_unknown_magic_bytes() {
+        assert!(matches_detected_type(b"just some notes", "application/pdf", "application/octet-stream", "pdf"));
+        assert!(matches_detected_type(b"", "image/png", "application/octet-stream", "png"));
+    }
+
+    #[test]
+    fn is_inline_previewable_allows_passive_types() {
+        for mime_type in ["image/png", "image/jpeg", "IMAGE/GIF", "application/pdf", "text/plain; charset=utf-8"] {
+            assert!(is_inline_previewable(mime_type), "{}", mime_type);
+        }
+    }
+
+    #[test]
+    fn is_inline_previewable_denies_scriptable_types() {
+        for mime_type in [
+            "text/html",
+            "text/html; charset=utf-8",
+            "image/svg+xml",
+            " Image/SVG+XML ",
+            "application/xhtml+xml",
+            "text/javascript",
+            "application/octet-stream",
+            "",
+        ] {
+            assert!(!is_inline_previewable(mime_type), "{}", mime_type);
+        }
+    }
+
+    #[test]
+    fn generate_qr_png_encodes_the_text() {
+        let url = "https://files.example.com/files/00000000-0000-0000-0000-000000000000/download";
+        let png = generate_qr_png(url, 200).unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+
+        let qr = image::load_from_memory_with_format(&png, image::ImageFormat::Png).unwrap().to_luma8();
+        assert!(qr.width() >= 200 && qr.height() >= 200);
+        assert_eq!(qr.width(), qr.height());
+
+        let mut prepared = rqrr::PreparedImage::prepare_from_greyscale(qr.width() as usize, qr.height() as usize, |x, y| {
+            qr.get_pixel(x as u32, y as u32).0[0]
+        });
+        let grids = prepared.detect_grids();
+        assert_eq!(grids.len(), 1);
+        let (_, content) = grids[0].decode().unwrap();
+        assert_eq!(content, url);
+    }
+
+    fn solid_png(width: u32, height: u32, color: [u8; 4]) -> Vec<u8> {
+        let img = image::RgbaImage::from_pixel(width, height, image::Rgba(color));
+        let mut output = Cursor::new(Vec::new());
+        img.write_to(&mut output, image::ImageFormat::Png).unwrap();
+        output.into_inner()
+    }
+
+    #[tokio::test]
+    async fn apply_watermark_overlays_without_resizing() {
+        let white = [255, 255, 255, 255];
+        let base = solid_png(90, 60, white);
+        let mark = std::sync::Arc::new(image::RgbaImage::from_pixel(10, 10, image::Rgba([255, 0, 0, 255])));
+
+        let output = apply_watermark(&base, mark, WatermarkPosition::BottomRight, 1.0, ConvertFormat::Png)
+            .await
+            .unwrap();
+        assert_ne!(output, base);
+
+        let marked = image::load_from_memory(&output).unwrap().to_rgba8();
+        assert_eq!(marked.dimensions(), (90, 60));
+        assert_eq!(marked.get_pixel(80, 50).0, [255, 0, 0, 255]);
+        assert_eq!(marked.get_pixel(5, 5).0, white);
+    }
+
+    #[tokio::test]
+    async fn apply_watermark_shrinks_oversized_marks() {
+        let base = solid_png(90, 60, [255, 255, 255, 255]);
+        let mark = std::sync::Arc::new(image::RgbaImage::from_pixel(300, 300, image::Rgba([0, 0, 0, 255])));
+
+        let output = apply_watermark(&base, mark, WatermarkPosition::TopLeft, 1.0, ConvertFormat::Png)
+            .await
+            .unwrap();
+        let marked = image::load_from_memory(&output).unwrap().to_rgba8();
+        assert_eq!(marked.dimensions(), (90, 60));
+        // At most a third of the width is covered
+        assert_eq!(marked.get_pixel(10, 10).0, [0, 0, 0, 255]);
+        assert_eq!(marked.get_pixel(60, 10).0, [255, 255, 255, 255]);
+    }
+
+    #[tokio::test]
+    async fn convert_image_round_trips_png() {
+        let png = solid_png(16, 8, [10, 200, 30, 128]);
+
+        for (format, expected) in [(ConvertFormat::Jpeg, image::ImageFormat::Jpeg), (ConvertFormat::Webp, image::ImageFormat::WebP)] {
+            let converted = convert_image(&png, format, 80).await.unwrap();
+            assert_eq!(image::guess_format(&converted).unwrap(), expected);
+            let decoded = image::load_from_memory_with_format(&converted, expected).unwrap();
+            assert_eq!((decoded.width(), decoded.height()), (16, 8));
+        }
+    }
+
+    #[tokio::test]
+    async fn convert_image_rejects_unsupported_input_and_targets() {
+        assert_eq!(ConvertFormat::parse("gif"), None);
+        assert!("tiff".parse::<ConvertFormat>().is_err());
+        assert_eq!("JPG".parse::<ConvertFormat>(), Ok(ConvertFormat::Jpeg));
+
+        assert!(convert_image(b"not an image", ConvertFormat::Png, 80).await.is_err());
+    }
+}